@@ -1,18 +1,748 @@
-[[block]]
-struct VoxelBuffer {
-    data: [[stride(4)]] array<u32>;
+// Falling-sand rules of the GPU backend of sandbase_core, see `gpu.rs`.
+//
+// A tick runs four passes over the whole map, one invocation per cell:
+// - choose: each voxel updates like `GameMap::update_cell` would on the map as it
+//   was at the start of the tick: it burns, ages, walks up to its velocity in
+//   cells or picks the neighbour it reacts with or sets fire to, then claims the
+//   cell it acts on
+// - resolve: each cell goes to its claimant the CPU would have updated first,
+//   unless the voxel in it is busy acting itself
+// - apply: winners move, react or set fire, swapped voxels take the place of the
+//   ones that left, burning voxels release smoke and flames above them
+// - conduct: heat is exchanged with the neighbours, then phases change
+//
+// Cells are stored row by row from the bottom-left corner, like `GameMap`.
+
+struct Params {
+    width: u32,
+    height: u32,
+    // 64-bit tick and seed of the map, as (low, high) halves
+    tick_low: u32,
+    tick_high: u32,
+    seed_low: u32,
+    seed_high: u32,
+    ambient_temperature: f32,
+    air_conductivity: f32,
+    // Constants of `GameMap`
+    gravity: f32,
+    splash_velocity: f32,
+    ignition_temperature: f32,
+    burn_temperature: f32,
+    smoke_chance: f32,
+    flame_chance: f32,
+    chunk_size: u32,
+    _padding: u32,
+};
+
+struct Reaction {
+    // Element ids + 1 of the neighbour and of what the two voxels turn into, 0 when
+    // they disappear
+    other: u32,
+    into: u32,
+    other_into: u32,
+    probability: f32,
+};
+
+struct Rule {
+    moves: array<vec2<i32>, 8>,
+    reactions: array<Reaction, 4>,
+    kind: u32,
+    density: f32,
+    dispersion: u32,
+    move_count: u32,
+    conductivity: f32,
+    cools_below: f32,
+    // Element id + 1, 0 when the element doesn't change phase
+    cools_into: u32,
+    heats_above: f32,
+    heats_into: u32,
+    lifetime: u32,
+    reaction_count: u32,
+    speed: f32,
+    temperature: f32,
+    flammability: f32,
+    burn_time: u32,
+    // Element ids + 1, 0 for none
+    burns_into: u32,
+    flames: u32,
+    _padding0: u32,
+    _padding1: u32,
+    _padding2: u32,
+};
+
+struct Cell {
+    // Element id + 1, 0 for empty cells
+    element: u32,
+    temperature: f32,
+    lifetime: u32,
+    // Bit 0: burning, bits 8 to 15: shade
+    flags: u32,
+    velocity: f32,
 };
 
-[[binding(0), group(0)]]
-var<storage> voxel_buffer: [[access(read_write)]] VoxelBuffer;
+struct Action {
+    // The voxel once updated, as it ends up wherever it goes
+    cell: Cell,
+    // Cell the voxel moves to, reacts with or sets fire to, NO_CELL if none
+    acted_on: i32,
+    kind: u32,
+    // Index of the reaction in the rule, for ACTION_REACT
+    reaction: u32,
+    // Element id + 1 released in the cell above by a burning voxel, 0 if none
+    released: u32,
+};
+
+const KIND_STATIC: u32 = 0u;
+const KIND_LIQUID: u32 = 2u;
+const KIND_GAS: u32 = 3u;
+const NO_LIFETIME: u32 = 0xffffffffu;
+const BURNING: u32 = 1u;
+const NO_CELL: i32 = -1;
+const ACTION_NONE: u32 = 0u;
+const ACTION_MOVE: u32 = 1u;
+const ACTION_REACT: u32 = 2u;
+const ACTION_IGNITE: u32 = 3u;
+// The voxel ran out of lifetime, its cell holds what it left
+const ACTION_REPLACED: u32 = 4u;
+const MAX_OVERRIDES: u32 = 32u;
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+@group(0) @binding(1)
+var<storage, read> rules: array<Rule>;
+@group(0) @binding(2)
+var<storage, read_write> cells: array<Cell>;
+// Cells after the moves of the tick, before heat is exchanged
+@group(0) @binding(3)
+var<storage, read_write> moved_cells: array<Cell>;
+// What the voxel of each cell does during the tick
+@group(0) @binding(4)
+var<storage, read_write> actions: array<Action>;
+// Claims on each cell. During choose, the priority of the best claimant, 0 for
+// none; after resolve, the index of the winner, NO_CELL for none. Cleared by
+// conduct for the next tick.
+@group(0) @binding(5)
+var<storage, read_write> claims: array<atomic<u32>>;
+// Number of voxels that moved since the counter was cleared
+@group(0) @binding(6)
+var<storage, read_write> moved: atomic<u32>;
+
+// State of the random generator of the invocation, see `seed_random`
+var<private> random_state: vec2<u32>;
+
+// Cells the voxel of the invocation changed while updating, see `cell_at`
+var<private> override_indices: array<i32, 32>;
+var<private> override_cells: array<Cell, 32>;
+var<private> override_count: u32;
+
+// 64-bit integers are stored as (low, high) halves
+fn add64(a: vec2<u32>, b: vec2<u32>) -> vec2<u32> {
+    let low = a.x + b.x;
+    return vec2<u32>(low, a.y + b.y + select(0u, 1u, low < a.x));
+}
+
+// Shifts right by 1 to 31 bits
+fn shift_right64(a: vec2<u32>, bits: u32) -> vec2<u32> {
+    return vec2<u32>(a.x >> bits | a.y << (32u - bits), a.y >> bits);
+}
+
+// High half of the 64-bit product of two 32-bit integers
+fn multiply_high32(a: u32, b: u32) -> u32 {
+    let a0 = a & 0xffffu;
+    let a1 = a >> 16u;
+    let b0 = b & 0xffffu;
+    let b1 = b >> 16u;
+    let middle = (a0 * b0 >> 16u) + (a0 * b1 & 0xffffu) + (a1 * b0 & 0xffffu);
+    return a1 * b1 + (a0 * b1 >> 16u) + (a1 * b0 >> 16u) + (middle >> 16u);
+}
+
+// Wrapping product
+fn multiply64(a: vec2<u32>, b: vec2<u32>) -> vec2<u32> {
+    return vec2<u32>(a.x * b.x, multiply_high32(a.x, b.x) + a.x * b.y + a.y * b.x);
+}
+
+// SplitMix64, like `Rng::next_u64`
+fn next_random() -> vec2<u32> {
+    random_state = add64(random_state, vec2<u32>(0x7f4a7c15u, 0x9e3779b9u));
+    var z = random_state;
+    z = multiply64(z ^ shift_right64(z, 30u), vec2<u32>(0x1ce4e5b9u, 0xbf58476du));
+    z = multiply64(z ^ shift_right64(z, 27u), vec2<u32>(0x133111ebu, 0x94d049bbu));
+    return z ^ shift_right64(z, 31u);
+}
+
+// Seeds the generator of the voxel at (x, y) like `Rng::for_cell`, so the voxel
+// draws the numbers it would draw on the CPU
+fn seed_random(x: i32, y: i32) {
+    let seed = vec2<u32>(params.seed_low, params.seed_high);
+    let tick = vec2<u32>(params.tick_low, params.tick_high);
+    let tick_bits = multiply64(tick, vec2<u32>(0x78bd642fu, 0xa0761d64u));
+    let x_bits = multiply64(vec2<u32>(u32(x), 0u), vec2<u32>(0xa0b428dbu, 0xe7037ed1u));
+    let y_bits = multiply64(vec2<u32>(u32(y), 0u), vec2<u32>(0x9c88c6e3u, 0x8ebc6af0u));
+    random_state = seed ^ tick_bits ^ x_bits ^ y_bits;
+    _ = next_random();
+}
+
+// Uniform value in 0..bound, like `Rng::below`
+fn random_below(bound: u32) -> u32 {
+    let value = next_random();
+    // 2^32 modulo bound
+    let high_weight = (0xffffffffu % bound + 1u) % bound;
+    return (value.y % bound * high_weight + value.x % bound) % bound;
+}
+
+// True with the given probability, like `Rng::chance`
+fn chance(probability: f32) -> bool {
+    return f32(next_random().y >> 8u) / 16777216.0 < probability;
+}
+
+fn in_map(x: i32, y: i32) -> bool {
+    return x >= 0 && y >= 0 && x < i32(params.width) && y < i32(params.height);
+}
+
+fn index_of(x: i32, y: i32) -> i32 {
+    return y * i32(params.width) + x;
+}
+
+fn position_of(index: i32) -> vec2<i32> {
+    return vec2<i32>(index % i32(params.width), index / i32(params.width));
+}
+
+fn empty_cell() -> Cell {
+    return Cell(0u, 0.0, NO_LIFETIME, 0u, 0.0);
+}
+
+// Content of a cell as the voxel of the invocation sees it: the map at the start
+// of the tick with its own changes so far, as the CPU updates a voxel in one go.
+fn cell_at(index: i32) -> Cell {
+    for (var i = 0u; i < override_count; i = i + 1u) {
+        if (override_indices[i] == index) {
+            return override_cells[i];
+        }
+    }
+    return cells[index];
+}
+
+// Voxels reach at most `TILE_MARGIN` cells, the overrides have room for their path.
+fn set_cell(index: i32, cell: Cell) {
+    for (var i = 0u; i < override_count; i = i + 1u) {
+        if (override_indices[i] == index) {
+            override_cells[i] = cell;
+            return;
+        }
+    }
+    if (override_count < MAX_OVERRIDES) {
+        override_indices[override_count] = index;
+        override_cells[override_count] = cell;
+        override_count = override_count + 1u;
+    }
+}
+
+fn is_empty(x: i32, y: i32) -> bool {
+    return in_map(x, y) && cell_at(index_of(x, y)).element == 0u;
+}
+
+fn is_element(element: u32, x: i32, y: i32) -> bool {
+    return in_map(x, y) && cell_at(index_of(x, y)).element == element + 1u;
+}
+
+// New voxel of `element`, an element id + 1, shaded with the numbers drawn for
+// (x, y) like `ElementRegistry::spawn_shaded_voxel`. An empty cell for 0.
+fn spawn(element: u32, x: i32, y: i32) -> Cell {
+    if (element == 0u) {
+        return empty_cell();
+    }
+    seed_random(x, y);
+    let rule = rules[element - 1u];
+    return Cell(element, rule.temperature, rule.lifetime, random_below(256u) << 8u, 0.0);
+}
+
+// Voxels enter empty cells, sink through lighter liquids and gases and rise
+// through heavier ones, like `Voxel::density_behaviour`.
+fn can_enter(element: u32, index: i32, dy: i32) -> bool {
+    let other = cell_at(index);
+    if (other.element == 0u) {
+        return true;
+    }
+    let density = rules[element].density;
+    let other_rule = rules[other.element - 1u];
+    let sinks = dy <= 0 && other_rule.density < density;
+    let rises = dy > 0 && other_rule.density > density;
+    let is_fluid = other_rule.kind == KIND_LIQUID || other_rule.kind == KIND_GAS;
+    return is_fluid && (sinks || rises);
+}
+
+// Flows up to `range` cells in the direction of `dx`, stopping at the first
+// obstacle or above the first hole, like `Voxel::disperse`.
+fn disperse(element: u32, x: i32, y: i32, dx: i32, range: u32) -> i32 {
+    var destination = NO_CELL;
+    for (var distance = 1; distance <= i32(range); distance = distance + 1) {
+        let new_x = x + dx * distance;
+        if (!in_map(new_x, y)) {
+            break;
+        }
+        let index = index_of(new_x, y);
+        if (cell_at(index).element == 0u) {
+            destination = index;
+            if (is_empty(new_x, y - 1)) {
+                break;
+            }
+        } else {
+            if (distance == 1 && can_enter(element, index, 0)) {
+                return index;
+            }
+            break;
+        }
+    }
+    return destination;
+}
+
+// The top voxel of a liquid body flows through the body to a free cell lower
+// than itself, scanning down its column, along a row, then up to the surface,
+// like `Voxel::level`.
+fn level(element: u32, x: i32, y: i32) -> i32 {
+    if (is_element(element, x, y + 1)) {
+        return NO_CELL;
+    }
+    let reach = i32(rules[element].dispersion);
+    for (var depth = 1; depth <= reach; depth = depth + 1) {
+        if (!is_element(element, x, y - depth)) {
+            break;
+        }
+        for (var direction = -1; direction <= 1; direction = direction + 2) {
+            for (var distance = 1; distance <= reach; distance = distance + 1) {
+                let body_x = x + direction * distance;
+                if (!is_element(element, body_x, y - depth)) {
+                    if (is_empty(body_x, y - depth)) {
+                        return index_of(body_x, y - depth);
+                    }
+                    break;
+                }
+                // Up to the surface, lower than the voxel
+                for (var dy = 1 - depth; dy < 0; dy = dy + 1) {
+                    if (is_empty(body_x, y + dy)) {
+                        return index_of(body_x, y + dy);
+                    }
+                    if (!is_element(element, body_x, y + dy)) {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    return NO_CELL;
+}
+
+// Cell the voxel at (x, y) moves to, like the moves of `Voxel::update`, NO_CELL
+// if it stays.
+fn move_of(element: u32, x: i32, y: i32) -> i32 {
+    let kind = rules[element].kind;
+    if (kind == KIND_STATIC) {
+        return NO_CELL;
+    }
+    let move_count = rules[element].move_count;
+
+    // Gases try their moves in a random order, shuffled like `Rng::shuffle`
+    var moves = rules[element].moves;
+    if (kind == KIND_GAS && move_count > 1u) {
+        seed_random(x, y);
+        for (var i = move_count - 1u; i > 0u; i = i - 1u) {
+            let other = random_below(i + 1u);
+            let offset = moves[i];
+            moves[i] = moves[other];
+            moves[other] = offset;
+        }
+    }
+    var leveled = false;
+    for (var i = 0u; i < move_count; i = i + 1u) {
+        let offset = moves[i];
+        var destination = NO_CELL;
+        if (offset.y == 0 && offset.x != 0) {
+            // Flowing down to a lower surface comes before spreading on this one
+            if (kind == KIND_LIQUID && !leveled) {
+                leveled = true;
+                destination = level(element, x, y);
+            }
+            if (destination == NO_CELL) {
+                destination = disperse(element, x, y, offset.x, rules[element].dispersion);
+            }
+        } else if (in_map(x + offset.x, y + offset.y)) {
+            let new_index = index_of(x + offset.x, y + offset.y);
+            if (can_enter(element, new_index, offset.y)) {
+                destination = new_index;
+            }
+        }
+        if (destination != NO_CELL) {
+            return destination;
+        }
+    }
+    if (kind == KIND_LIQUID && !leveled) {
+        return level(element, x, y);
+    }
+    return NO_CELL;
+}
+
+// Neighbour the voxel at (x, y) reacts with, like `Voxel::react`: its index and
+// the index of the reaction, NO_CELL if none.
+fn reaction_of(element: u32, x: i32, y: i32) -> vec2<i32> {
+    let count = rules[element].reaction_count;
+    if (count == 0u) {
+        return vec2<i32>(NO_CELL, 0);
+    }
+    seed_random(x, y);
+    var neighbours = array<vec2<i32>, 4>(
+        vec2<i32>(0, -1),
+        vec2<i32>(-1, 0),
+        vec2<i32>(1, 0),
+        vec2<i32>(0, 1)
+    );
+    for (var n = 0; n < 4; n = n + 1) {
+        let other_x = x + neighbours[n].x;
+        let other_y = y + neighbours[n].y;
+        if (!in_map(other_x, other_y)) {
+            continue;
+        }
+        let index = index_of(other_x, other_y);
+        let other = cell_at(index).element;
+        if (other == 0u) {
+            continue;
+        }
+        for (var r = 0u; r < count; r = r + 1u) {
+            let reaction = rules[element].reactions[r];
+            // Numbers are only drawn where the CPU draws them, and naga doesn't
+            // short-circuit `&&`
+            if (reaction.other == other) {
+                if (chance(reaction.probability)) {
+                    return vec2<i32>(index, i32(r));
+                }
+            }
+        }
+    }
+    return vec2<i32>(NO_CELL, 0);
+}
+
+// Neighbour the voxel at (x, y) sets fire to, like `Voxel::ignite`, NO_CELL if
+// none.
+fn ignition_of(x: i32, y: i32) -> i32 {
+    seed_random(x, y);
+    var neighbours = array<vec2<i32>, 4>(
+        vec2<i32>(0, 1),
+        vec2<i32>(-1, 0),
+        vec2<i32>(1, 0),
+        vec2<i32>(0, -1)
+    );
+    for (var n = 0; n < 4; n = n + 1) {
+        let other_x = x + neighbours[n].x;
+        let other_y = y + neighbours[n].y;
+        if (!in_map(other_x, other_y)) {
+            continue;
+        }
+        let index = index_of(other_x, other_y);
+        let other = cell_at(index);
+        if (other.element != 0u && (other.flags & BURNING) == 0u) {
+            if (chance(rules[other.element - 1u].flammability)) {
+                return index;
+            }
+        }
+    }
+    return NO_CELL;
+}
+
+fn chunk_count() -> u32 {
+    let size = params.chunk_size;
+    return ((params.width + size - 1u) / size) * ((params.height + size - 1u) / size);
+}
+
+// Position of the cell in the order the CPU updates them: chunks by checkerboard
+// pass, then rows bottom-up, each row in the direction given by its parity and
+// the tick.
+fn update_order(x: i32, y: i32) -> u32 {
+    let size = params.chunk_size;
+    let chunks_width = (params.width + size - 1u) / size;
+    let chunk_x = u32(x) / size;
+    let chunk_y = u32(y) / size;
+    let checkerboard_pass = chunk_x % 2u + 2u * (chunk_y % 2u);
+    var column = u32(x) % size;
+    if ((u32(y) + params.tick_low) % 2u != 0u) {
+        column = size - 1u - column;
+    }
+    let chunk = checkerboard_pass * chunk_count() + chunk_y * chunks_width + chunk_x;
+    return (chunk * size + u32(y) % size) * size + column;
+}
+
+// Inverse of `update_order`
+fn ordered_position(order: u32) -> vec2<i32> {
+    let size = params.chunk_size;
+    let chunks_width = (params.width + size - 1u) / size;
+    let chunk = order / (size * size) % chunk_count();
+    let y = chunk / chunks_width * size + order / size % size;
+    var column = order % size;
+    if ((y + params.tick_low) % 2u != 0u) {
+        column = size - 1u - column;
+    }
+    return vec2<i32>(i32(chunk % chunks_width * size + column), i32(y));
+}
+
+// Claims the cell at `index` for the voxel at (x, y), the voxels the CPU updates first
+// having the highest priority.
+fn claim(index: i32, x: i32, y: i32) {
+    let priority = 4u * chunk_count() * params.chunk_size * params.chunk_size - update_order(x, y);
+    atomicMax(&claims[index], priority);
+}
+
+// Stores the action of the voxel at (x, y) and claims the cells it acts on. The
+// release of a burning voxel gives way to the voxel's own action on that cell.
+fn finish(x: i32, y: i32, action: Action) {
+    var next = action;
+    let above = index_of(x, y + 1);
+    if (next.released != 0u && next.acted_on == above) {
+        next.released = 0u;
+    }
+    if (next.acted_on != NO_CELL) {
+        claim(next.acted_on, x, y);
+    }
+    if (next.released != 0u) {
+        claim(above, x, y);
+    }
+    actions[index_of(x, y)] = next;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn choose(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    if (invocation_id.x >= params.width || invocation_id.y >= params.height) {
+        return;
+    }
+    let x = i32(invocation_id.x);
+    let y = i32(invocation_id.y);
+    let index = index_of(x, y);
+    var cell = cells[index];
+    var action = Action(cell, NO_CELL, ACTION_NONE, 0u, 0u);
+    if (cell.element == 0u) {
+        actions[index] = action;
+        return;
+    }
+    let element = cell.element - 1u;
+    override_count = 0u;
+
+    // Burning voxels stay hot and now and then release smoke or flames above them,
+    // like `GameMap::burn`
+    let burning = (cell.flags & BURNING) != 0u;
+    if (burning) {
+        cell.temperature = max(cell.temperature, params.burn_temperature);
+        if (is_empty(x, y + 1)) {
+            seed_random(x, y);
+            if (chance(params.smoke_chance)) {
+                action.released = rules[element].burns_into;
+            } else if (chance(params.flame_chance)) {
+                action.released = rules[element].flames;
+            }
+            if (action.released != 0u) {
+                set_cell(index_of(x, y + 1), spawn(action.released, x, y + 1));
+            }
+        }
+    }
+    if (cell.lifetime != NO_LIFETIME) {
+        if (cell.lifetime == 0u) {
+            action.cell = empty_cell();
+            if (burning) {
+                action.cell = spawn(rules[element].burns_into, x, y);
+            }
+            action.kind = ACTION_REPLACED;
+            finish(x, y, action);
+            return;
+        }
+        cell.lifetime = cell.lifetime - 1u;
+    }
+
+    // One cell at a time, like the steps of `GameMap::update_cell`
+    let steps = max(1u, u32(cell.velocity));
+    var position = vec2<i32>(x, y);
+    var falls = 0u;
+    var reacting = false;
+    for (var i = 0u; i < steps; i = i + 1u) {
+        let reaction = reaction_of(element, position.x, position.y);
+        var other = reaction.x;
+        var kind = ACTION_REACT;
+        if (other == NO_CELL && cell.temperature >= params.ignition_temperature) {
+            other = ignition_of(position.x, position.y);
+            kind = ACTION_IGNITE;
+        }
+        if (other != NO_CELL) {
+            if (i == 0u) {
+                action.cell = cell;
+                action.acted_on = other;
+                action.kind = kind;
+                action.reaction = u32(reaction.y);
+                finish(x, y, action);
+                return;
+            }
+            // A voxel acts on one cell per tick: having moved, it reacts next tick
+            reacting = true;
+            break;
+        }
+        let destination = move_of(element, position.x, position.y);
+        if (destination == NO_CELL) {
+            break;
+        }
+        // Swapped voxels take the place the voxel leaves
+        set_cell(index_of(position.x, position.y), cell_at(destination));
+        set_cell(destination, cell);
+        let destination_position = position_of(destination);
+        if (destination_position.y < position.y) {
+            falls = falls + 1u;
+        }
+        position = destination_position;
+    }
+
+    // Falling voxels speed up, stopped ones lose their momentum and may splash
+    if (falls == steps) {
+        cell.velocity = min(cell.velocity + params.gravity, rules[element].speed);
+    } else {
+        let kind = rules[element].kind;
+        if (!reacting && cell.velocity >= params.splash_velocity && kind != KIND_STATIC
+            && kind != KIND_GAS) {
+            seed_random(position.x, position.y);
+            var dx = 1;
+            if (chance(0.5)) {
+                dx = -1;
+            }
+            let range = u32(cell.velocity / 2.0);
+            let destination = disperse(element, position.x, position.y, dx, range);
+            if (destination != NO_CELL) {
+                position = position_of(destination);
+            }
+        }
+        cell.velocity = 0.0;
+    }
+    action.cell = cell;
+    if (position.x != x || position.y != y) {
+        action.acted_on = index_of(position.x, position.y);
+        action.kind = ACTION_MOVE;
+    }
+    finish(x, y, action);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn resolve(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    if (invocation_id.x >= params.width || invocation_id.y >= params.height) {
+        return;
+    }
+    let index = index_of(i32(invocation_id.x), i32(invocation_id.y));
+    let priority = atomicLoad(&claims[index]);
+    var winner = NO_CELL;
+    // Voxels moving, reacting or disappearing can't be acted on
+    if (priority != 0u && (cells[index].element == 0u || actions[index].kind == ACTION_NONE)) {
+        let order = 4u * chunk_count() * params.chunk_size * params.chunk_size - priority;
+        let position = ordered_position(order);
+        winner = index_of(position.x, position.y);
+    }
+    atomicStore(&claims[index], bitcast<u32>(winner));
+}
+
+fn winner_of(index: i32) -> i32 {
+    return bitcast<i32>(atomicLoad(&claims[index]));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn apply(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    if (invocation_id.x >= params.width || invocation_id.y >= params.height) {
+        return;
+    }
+    let x = i32(invocation_id.x);
+    let y = i32(invocation_id.y);
+    let index = index_of(x, y);
+    let action = actions[index];
+    var next = action.cell;
+    let winner = winner_of(index);
+    if (winner != NO_CELL) {
+        let claimant = actions[winner];
+        if (claimant.acted_on != index) {
+            // Released by the burning voxel below
+            next = spawn(claimant.released, x, y);
+        } else if (claimant.kind == ACTION_MOVE) {
+            next = claimant.cell;
+        } else if (claimant.kind == ACTION_REACT) {
+            let reaction = rules[claimant.cell.element - 1u].reactions[claimant.reaction];
+            next = spawn(reaction.other_into, x, y);
+        } else if (next.element != 0u) {
+            // Set on fire, like `GameMap::apply_move`
+            next.flags = next.flags | BURNING;
+            next.lifetime = rules[next.element - 1u].burn_time;
+        }
+    } else if (action.kind == ACTION_REPLACED) {
+        atomicAdd(&moved, 1u);
+    } else if (action.kind != ACTION_NONE) {
+        if (winner_of(action.acted_on) == index) {
+            atomicAdd(&moved, 1u);
+            if (action.kind == ACTION_MOVE) {
+                // The content of the destination takes the place of the voxel
+                next = empty_cell();
+                if (cells[action.acted_on].element != 0u) {
+                    next = actions[action.acted_on].cell;
+                }
+            } else if (action.kind == ACTION_REACT) {
+                let reaction = rules[action.cell.element - 1u].reactions[action.reaction];
+                next = spawn(reaction.into, x, y);
+            }
+        } else if (action.kind == ACTION_MOVE) {
+            // Blocked by a voxel the CPU updates first
+            next.velocity = 0.0;
+        }
+    }
+    moved_cells[index] = next;
+}
+
+// Heat flowing into a voxel from the cell at (x, y), like
+// `GameMap::update_temperatures`: empty cells are air at the ambient temperature.
+fn heat_from(temperature: f32, conductivity: f32, x: i32, y: i32) -> f32 {
+    if (!in_map(x, y)) {
+        return 0.0;
+    }
+    let other = moved_cells[index_of(x, y)];
+    var other_temperature = params.ambient_temperature;
+    var other_conductivity = params.air_conductivity;
+    if (other.element != 0u) {
+        other_temperature = other.temperature;
+        other_conductivity = rules[other.element - 1u].conductivity;
+    }
+    return (other_temperature - temperature) * min(conductivity, other_conductivity) / 4.0;
+}
 
-[[stage(compute), workgroup_size(16, 16)]]
-fn main([[builtin(global_invocation_id)]] global_id: vec3<u32>) {
-    let x: u32 = global_id.x;
-    let y: u32 = global_id.y;
+@compute @workgroup_size(8, 8, 1)
+fn conduct(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    if (invocation_id.x >= params.width || invocation_id.y >= params.height) {
+        return;
+    }
+    let x = i32(invocation_id.x);
+    let y = i32(invocation_id.y);
+    let index = index_of(x, y);
+    atomicStore(&claims[index], 0u);
+    var next = moved_cells[index];
+    if (next.element == 0u) {
+        cells[index] = next;
+        return;
+    }
 
-    // Add your cellular automata logic here
+    let rule = rules[next.element - 1u];
+    var temperature = next.temperature;
+    if (rule.conductivity > 0.0) {
+        temperature = temperature + heat_from(next.temperature, rule.conductivity, x, y - 1);
+        temperature = temperature + heat_from(next.temperature, rule.conductivity, x - 1, y);
+        temperature = temperature + heat_from(next.temperature, rule.conductivity, x + 1, y);
+        temperature = temperature + heat_from(next.temperature, rule.conductivity, x, y + 1);
+    }
+    next.temperature = temperature;
 
-    // Write the result back to the voxel buffer
-    voxel_buffer.data[y * 16 + x] = result;
-}
\ No newline at end of file
+    var element = 0u;
+    if (rule.cools_into != 0u && temperature < rule.cools_below) {
+        element = rule.cools_into;
+    } else if (rule.heats_into != 0u && temperature > rule.heats_above) {
+        element = rule.heats_into;
+    }
+    if (element != 0u) {
+        next.element = element;
+        next.lifetime = rules[element - 1u].lifetime;
+        next.flags = next.flags & ~BURNING;
+        next.velocity = 0.0;
+    }
+    cells[index] = next;
+}
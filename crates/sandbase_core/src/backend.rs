@@ -0,0 +1,120 @@
+use std::fmt;
+
+use crate::map::GameMap;
+
+/// Why a backend couldn't advance a map. The map is left as it was.
+#[derive(Debug)]
+pub enum SimulationError {
+    /// An element follows a rule the backend doesn't simulate.
+    UnsupportedElement { element: String, rule: &'static str },
+    /// The device running the backend failed, such as a lost GPU.
+    Device(String),
+}
+
+impl fmt::Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimulationError::UnsupportedElement { element, rule } => {
+                write!(
+                    f,
+                    "{:?} uses {}, which the backend can't simulate",
+                    element, rule
+                )
+            }
+            SimulationError::Device(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for SimulationError {}
+
+/// Runs the element rules over a map. [`CpuBackend`] steps the map itself, the
+/// `gpu` feature adds a backend running them in compute shaders.
+pub trait SimulationBackend: Send + Sync {
+    /// Name shown to players, e.g. in logs.
+    fn name(&self) -> &str;
+
+    /// Advances `map` by `ticks` ticks. Returns the number of voxels that moved.
+    fn run(&mut self, map: &mut GameMap, ticks: usize) -> Result<usize, SimulationError>;
+}
+
+/// Runs [`GameMap::step`], the reference implementation of the rules.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CpuBackend;
+
+impl SimulationBackend for CpuBackend {
+    fn name(&self) -> &str {
+        "cpu"
+    }
+
+    fn run(&mut self, map: &mut GameMap, ticks: usize) -> Result<usize, SimulationError> {
+        Ok((0..ticks).map(|_| map.step()).sum())
+    }
+}
+
+/// Backend the front-end runs the simulation with, the CPU one by default.
+#[cfg_attr(feature = "bevy", derive(bevy_ecs::system::Resource))]
+pub struct Simulation {
+    backend: Box<dyn SimulationBackend>,
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Simulation::new(CpuBackend)
+    }
+}
+
+impl Simulation {
+    pub fn new(backend: impl SimulationBackend + 'static) -> Self {
+        Simulation {
+            backend: Box::new(backend),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.backend.name()
+    }
+
+    /// Advances `map` by `ticks` ticks. Returns the number of voxels that moved.
+    pub fn run(&mut self, map: &mut GameMap, ticks: usize) -> Result<usize, SimulationError> {
+        self.backend.run(map, ticks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::voxel;
+    use crate::world_position::WorldPosition;
+
+    #[test]
+    fn cpu_backend_steps_the_map() {
+        let mut map = GameMap::new(4, 8);
+        map.set_cell(&WorldPosition::new(1, 7), &voxel("sand"));
+        let mut stepped = map.clone();
+        for _ in 0..5 {
+            stepped.step();
+        }
+
+        let moved = Simulation::default().run(&mut map, 5).unwrap();
+
+        assert!(moved > 0);
+        assert_eq!(map.tick(), 5);
+        assert_eq!(
+            map.iter().collect::<Vec<_>>(),
+            stepped.iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn gpu_shader_is_valid() {
+        let source = include_str!("../../../assets/shaders/automata.wgsl");
+        let module = naga::front::wgsl::parse_str(source).unwrap();
+        naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::empty(),
+        )
+        .validate(&module)
+        .unwrap();
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::HashSet;
+
+use crate::elements::Element;
+use crate::map::GameMap;
+use crate::rng::Rng;
+use crate::voxels::Voxel;
+use crate::world_position::WorldPosition;
+
+/// Largest brush radius, in cells.
+pub const MAX_RADIUS: usize = 32;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BrushShape {
+    Circle,
+    Square,
+    /// Straight line from where the stroke starts to where it ends, as thick as a
+    /// circle of the brush radius.
+    Line,
+}
+
+impl BrushShape {
+    /// The following shape, back to the first one after the last.
+    pub fn next(self) -> Self {
+        match self {
+            BrushShape::Circle => BrushShape::Square,
+            BrushShape::Square => BrushShape::Line,
+            BrushShape::Line => BrushShape::Circle,
+        }
+    }
+}
+
+/// Cells covered around the cursor when painting or erasing.
+///
+/// A stroke stamps the brush on every cell between two cursor positions, so fast
+/// mouse moves leave no gaps. Below a `density` of 1, the brush sprays: only some
+/// of the covered cells are painted, each up to `scatter` cells away.
+#[cfg_attr(feature = "bevy", derive(bevy_ecs::system::Resource))]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Brush {
+    pub shape: BrushShape,
+    /// Cells between the center and the edge, 0 for a single cell.
+    pub radius: usize,
+    /// Share of the covered cells painted, between 0 and 1.
+    pub density: f32,
+    /// Farthest a sprayed cell lands from the cell it was aimed at, in cells.
+    pub scatter: usize,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Brush {
+            shape: BrushShape::Circle,
+            radius: 2,
+            density: 1.,
+            scatter: 0,
+        }
+    }
+}
+
+impl Brush {
+    /// Cells covered by the brush centered on `center`. Lines are stamped as circles.
+    pub fn stamp(&self, center: WorldPosition) -> Vec<WorldPosition> {
+        let radius = self.radius.min(MAX_RADIUS) as isize;
+        let mut cells = Vec::new();
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                // Rounder than dx² + dy² <= r² for small radiuses
+                let inside =
+                    self.shape == BrushShape::Square || dx * dx + dy * dy <= radius * (radius + 1);
+                if let Some(cell) = center.offset(dx, dy).filter(|_| inside) {
+                    cells.push(cell);
+                }
+            }
+        }
+        cells
+    }
+
+    /// Cells covered by the brush dragged from `from` to `to`, each once.
+    pub fn stroke(&self, from: WorldPosition, to: WorldPosition) -> Vec<WorldPosition> {
+        let mut seen = HashSet::new();
+        line(from, to)
+            .into_iter()
+            .flat_map(|center| self.stamp(center))
+            .filter(|cell| seen.insert(*cell))
+            .collect()
+    }
+
+    /// Cells actually painted among the covered `cells`.
+    pub fn spray(&self, cells: &[WorldPosition], rng: &mut Rng) -> Vec<WorldPosition> {
+        let mut sprayed = Vec::with_capacity(cells.len());
+        for cell in cells {
+            if self.density < 1. && !rng.chance(self.density) {
+                continue;
+            }
+            let mut offset = || rng.below(2 * self.scatter + 1) as isize - self.scatter as isize;
+            let (dx, dy) = (offset(), offset());
+            sprayed.extend(cell.offset(dx, dy));
+        }
+        sprayed
+    }
+
+    /// Spawns voxels of `element` in the empty cells sprayed by a stroke from `from`
+    /// to `to`. Returns the number of voxels spawned.
+    pub fn paint(
+        &self,
+        map: &mut GameMap,
+        from: WorldPosition,
+        to: WorldPosition,
+        element: Element,
+        rng: &mut Rng,
+    ) -> usize {
+        let mut painted = 0;
+        for cell in self.spray(&self.stroke(from, to), rng) {
+            if map.contains(&cell) && map.get_cell(&cell).is_none() {
+                map.spawn_cell(&cell, element);
+                painted += 1;
+            }
+        }
+        painted
+    }
+
+    /// Deletes the voxels covered by a stroke from `from` to `to`, whatever the spray
+    /// density. Returns the number of voxels deleted.
+    pub fn erase(&self, map: &mut GameMap, from: WorldPosition, to: WorldPosition) -> usize {
+        let mut erased = 0;
+        for cell in self.stroke(from, to) {
+            if let Some(Voxel::of { .. }) = map.get_cell(&cell) {
+                map.delete_cell(&cell);
+                erased += 1;
+            }
+        }
+        erased
+    }
+}
+
+/// Cells of `cells` on the edge of the area they cover, to preview a brush.
+pub fn outline(cells: &[WorldPosition]) -> Vec<WorldPosition> {
+    let area: HashSet<_> = cells.iter().collect();
+    cells
+        .iter()
+        .filter(|cell| {
+            [(0, -1), (-1, 0), (1, 0), (0, 1)].iter().any(|(dx, dy)| {
+                cell.offset(*dx, *dy)
+                    .is_none_or(|neighbour| !area.contains(&neighbour))
+            })
+        })
+        .copied()
+        .collect()
+}
+
+/// Cells of the segment from `from` to `to`, both included (Bresenham).
+fn line(from: WorldPosition, to: WorldPosition) -> Vec<WorldPosition> {
+    let (mut x, mut y) = (from.x as isize, from.y as isize);
+    let (to_x, to_y) = (to.x as isize, to.y as isize);
+    let (dx, dy) = ((to_x - x).abs(), -(to_y - y).abs());
+    let (step_x, step_y) = ((to_x - x).signum(), (to_y - y).signum());
+    let mut error = dx + dy;
+    let mut cells = vec![from];
+    while (x, y) != (to_x, to_y) {
+        if 2 * error >= dy {
+            error += dy;
+            x += step_x;
+        }
+        if 2 * error <= dx {
+            error += dx;
+            y += step_y;
+        }
+        cells.push(WorldPosition::new(x as usize, y as usize));
+    }
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn brush(shape: BrushShape, radius: usize) -> Brush {
+        Brush {
+            shape,
+            radius,
+            ..Brush::default()
+        }
+    }
+
+    #[test]
+    fn circles_are_smaller_than_squares() {
+        let center = WorldPosition::new(10, 10);
+
+        assert_eq!(brush(BrushShape::Square, 3).stamp(center).len(), 49);
+        let circle = brush(BrushShape::Circle, 3).stamp(center);
+        assert!(circle.len() < 49);
+        assert!(circle.contains(&WorldPosition::new(13, 10)));
+        assert!(!circle.contains(&WorldPosition::new(13, 13)));
+        assert_eq!(brush(BrushShape::Circle, 0).stamp(center), vec![center]);
+    }
+
+    #[test]
+    fn strokes_leave_no_gaps_and_stay_in_the_world() {
+        let stroke =
+            brush(BrushShape::Line, 0).stroke(WorldPosition::new(0, 0), WorldPosition::new(9, 3));
+
+        assert_eq!(stroke.len(), 10);
+        assert!(stroke.contains(&WorldPosition::new(9, 3)));
+        // Cells left of the world are dropped
+        assert_eq!(
+            brush(BrushShape::Square, 1)
+                .stamp(WorldPosition::new(0, 0))
+                .len(),
+            4
+        );
+    }
+
+    #[test]
+    fn sprays_paint_part_of_the_empty_cells() {
+        let mut map = GameMap::new(20, 20);
+        let sand = map.registry().find("sand").unwrap();
+        let center = WorldPosition::new(10, 10);
+        let spray = Brush {
+            density: 0.5,
+            scatter: 1,
+            ..brush(BrushShape::Square, 4)
+        };
+
+        let painted = spray.paint(&mut map, center, center, sand, &mut Rng::new(3));
+
+        assert!(painted > 0 && painted < 81);
+        assert_eq!(map.iter().count(), painted);
+        // Painting again only fills the cells left empty
+        let solid = brush(BrushShape::Square, 4);
+        solid.paint(&mut map, center, center, sand, &mut Rng::new(3));
+        assert!(solid
+            .stamp(center)
+            .iter()
+            .all(|cell| map.get_cell(cell).is_some()));
+    }
+
+    #[test]
+    fn erasing_empties_the_covered_cells_only() {
+        let mut map = GameMap::new(20, 20);
+        let sand = map.registry().find("sand").unwrap();
+        let solid = brush(BrushShape::Square, 5);
+        solid.paint(
+            &mut map,
+            WorldPosition::new(10, 10),
+            WorldPosition::new(10, 10),
+            sand,
+            &mut Rng::new(0),
+        );
+        map.take_changes();
+
+        let erased = brush(BrushShape::Line, 0).erase(
+            &mut map,
+            WorldPosition::new(5, 10),
+            WorldPosition::new(15, 10),
+        );
+
+        assert_eq!(erased, 11);
+        assert_eq!(map.iter().count(), 121 - 11);
+        assert_eq!(map.get_cell(&WorldPosition::new(8, 10)), None);
+        // The erased cells are redrawn
+        assert_eq!(map.take_changes().len(), 11);
+    }
+
+    #[test]
+    fn outlines_keep_the_edge_cells() {
+        let square = brush(BrushShape::Square, 2).stamp(WorldPosition::new(5, 5));
+
+        let edge = outline(&square);
+
+        assert_eq!(edge.len(), 16);
+        assert!(!edge.contains(&WorldPosition::new(5, 5)));
+    }
+}
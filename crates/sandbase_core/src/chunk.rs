@@ -0,0 +1,131 @@
+use crate::world_position::WorldPosition;
+
+/// Side of the square chunks the map is split into, in cells.
+pub const CHUNK_SIZE: usize = 32;
+
+/// Rectangle of cells, bounds included.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DirtyRect {
+    pub min: WorldPosition,
+    pub max: WorldPosition,
+}
+
+impl DirtyRect {
+    pub fn new(min: WorldPosition, max: WorldPosition) -> Self {
+        DirtyRect { min, max }
+    }
+
+    pub fn contains(&self, world_position: &WorldPosition) -> bool {
+        (self.min.x..=self.max.x).contains(&world_position.x)
+            && (self.min.y..=self.max.y).contains(&world_position.y)
+    }
+
+    /// `rect` grown by `margin` cells on every side, without going out of `bounds`.
+    pub fn around(rect: &DirtyRect, margin: usize, bounds: &DirtyRect) -> DirtyRect {
+        DirtyRect {
+            min: WorldPosition::new(
+                rect.min.x.saturating_sub(margin).max(bounds.min.x),
+                rect.min.y.saturating_sub(margin).max(bounds.min.y),
+            ),
+            max: WorldPosition::new(
+                (rect.max.x + margin).min(bounds.max.x),
+                (rect.max.y + margin).min(bounds.max.y),
+            ),
+        }
+    }
+
+    /// Smallest rectangle containing both rectangles.
+    pub fn union(&self, other: &DirtyRect) -> DirtyRect {
+        DirtyRect {
+            min: WorldPosition::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y)),
+            max: WorldPosition::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y)),
+        }
+    }
+
+    /// Cells in both rectangles, `None` if they don't overlap.
+    pub fn intersection(&self, other: &DirtyRect) -> Option<DirtyRect> {
+        let min = WorldPosition::new(self.min.x.max(other.min.x), self.min.y.max(other.min.y));
+        let max = WorldPosition::new(self.max.x.min(other.max.x), self.max.y.min(other.max.y));
+        if min.x <= max.x && min.y <= max.y {
+            Some(DirtyRect { min, max })
+        } else {
+            None
+        }
+    }
+}
+
+/// Square area of the map simulated as a unit. Only the cells of its dirty
+/// rectangle are updated, and the chunk sleeps once a tick goes by without any of
+/// them changing.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Chunk {
+    /// Cells updated during the current tick, `None` when the chunk sleeps.
+    pub dirty: Option<DirtyRect>,
+    /// Cells woken up during the current tick, updated during the next one.
+    next_dirty: Option<DirtyRect>,
+}
+
+impl Chunk {
+    /// Whether the chunk will be updated during the next tick.
+    pub fn is_awake(&self) -> bool {
+        self.next_dirty.is_some()
+    }
+
+    /// Cells woken up during the current tick.
+    pub fn woken(&self) -> Option<DirtyRect> {
+        self.next_dirty
+    }
+
+    /// Schedules the cells of `rect` for an update during the next tick.
+    pub fn wake(&mut self, rect: DirtyRect) {
+        self.next_dirty = Some(match self.next_dirty {
+            Some(next_dirty) => next_dirty.union(&rect),
+            None => rect,
+        });
+    }
+
+    /// Starts a tick: the cells woken up during the previous one become dirty.
+    pub fn start_tick(&mut self) {
+        self.dirty = self.next_dirty.take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(min: (usize, usize), max: (usize, usize)) -> DirtyRect {
+        DirtyRect::new(
+            WorldPosition::new(min.0, min.1),
+            WorldPosition::new(max.0, max.1),
+        )
+    }
+
+    #[test]
+    fn woken_cells_are_dirty_during_the_next_tick_only() {
+        let mut chunk = Chunk::default();
+        chunk.wake(rect((1, 1), (2, 2)));
+        chunk.wake(rect((4, 0), (5, 1)));
+        assert!(chunk.is_awake());
+        assert_eq!(chunk.dirty, None);
+
+        chunk.start_tick();
+        assert_eq!(chunk.dirty, Some(rect((1, 0), (5, 2))));
+        assert!(!chunk.is_awake());
+
+        chunk.start_tick();
+        assert_eq!(chunk.dirty, None);
+    }
+
+    #[test]
+    fn intersection_of_disjoint_rects_is_empty() {
+        assert_eq!(
+            rect((0, 0), (3, 3)).intersection(&rect((4, 0), (5, 3))),
+            None
+        );
+        assert_eq!(
+            rect((0, 0), (3, 3)).intersection(&rect((2, 1), (5, 2))),
+            Some(rect((2, 1), (3, 2)))
+        );
+    }
+}
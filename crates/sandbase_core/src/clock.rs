@@ -0,0 +1,115 @@
+/// Fixed-timestep clock driving the simulation independently of the frame rate.
+///
+/// Front-ends feed it the elapsed real time through [`SimulationClock::advance`] and
+/// run as many [`GameMap::step`](crate::GameMap::step) as it returns.
+#[cfg_attr(feature = "bevy", derive(bevy_ecs::system::Resource))]
+#[derive(Clone, Debug)]
+pub struct SimulationClock {
+    pub ticks_per_second: f32,
+    /// Multiplier applied to the elapsed time, `4.` runs the simulation four times
+    /// faster.
+    pub fast_forward: f32,
+    /// Upper bound of ticks run by a single `advance`, the remaining time is dropped
+    /// so a slow frame doesn't snowball into slower and slower frames.
+    pub max_ticks_per_advance: u32,
+    paused: bool,
+    pending_steps: u32,
+    accumulator: f32,
+}
+
+impl Default for SimulationClock {
+    fn default() -> Self {
+        SimulationClock::new(60.)
+    }
+}
+
+impl SimulationClock {
+    pub fn new(ticks_per_second: f32) -> Self {
+        SimulationClock {
+            ticks_per_second,
+            fast_forward: 1.,
+            max_ticks_per_advance: 8,
+            paused: false,
+            pending_steps: 0,
+            accumulator: 0.,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.accumulator = 0.;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        if self.paused {
+            self.resume()
+        } else {
+            self.pause()
+        }
+    }
+
+    /// Requests a single tick, mostly useful while paused.
+    pub fn step_once(&mut self) {
+        self.pending_steps += 1;
+    }
+
+    /// Accounts for `delta_seconds` of real time and returns the number of ticks to
+    /// simulate now.
+    pub fn advance(&mut self, delta_seconds: f32) -> u32 {
+        let pending_steps = std::mem::take(&mut self.pending_steps);
+        if self.paused || self.ticks_per_second <= 0. {
+            return pending_steps;
+        }
+
+        let tick_duration = 1. / self.ticks_per_second;
+        self.accumulator += delta_seconds * self.fast_forward;
+        let ticks = (self.accumulator / tick_duration) as u32;
+        self.accumulator -= ticks as f32 * tick_duration;
+        if ticks > self.max_ticks_per_advance {
+            self.accumulator = 0.;
+        }
+        ticks.min(self.max_ticks_per_advance) + pending_steps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticks_follow_elapsed_time_not_frames() {
+        let mut clock = SimulationClock::new(10.);
+
+        let ticks: u32 = (0..12).map(|_| clock.advance(1. / 120.)).sum();
+
+        assert_eq!(ticks, 1);
+    }
+
+    #[test]
+    fn paused_clock_only_runs_requested_steps() {
+        let mut clock = SimulationClock::new(10.);
+        clock.pause();
+
+        assert_eq!(clock.advance(1.), 0);
+        clock.step_once();
+        assert_eq!(clock.advance(1.), 1);
+        assert_eq!(clock.advance(1.), 0);
+    }
+
+    #[test]
+    fn fast_forward_runs_more_ticks_but_never_more_than_the_cap() {
+        let mut clock = SimulationClock::new(10.);
+        clock.fast_forward = 4.;
+
+        assert_eq!(clock.advance(0.1), 4);
+        assert_eq!(clock.advance(10.), clock.max_ticks_per_advance);
+    }
+}
@@ -0,0 +1,472 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::map::TILE_MARGIN;
+use crate::rng::Rng;
+use crate::voxels::{Voxel, VoxelStruct};
+
+/// Elements shipped with the game, embedded so headless users don't need the
+/// game assets.
+const DEFAULT_ELEMENTS: &str = include_str!("../../../assets/elements.ron");
+
+/// Temperature of the air and default temperature of new voxels, in °C.
+pub const AMBIENT_TEMPERATURE: f32 = 20.;
+
+/// Voxels at least this hot set fire to their flammable neighbours, in °C.
+pub const IGNITION_TEMPERATURE: f32 = 400.;
+
+/// Temperature burning voxels are kept at, in °C.
+pub const BURN_TEMPERATURE: f32 = 800.;
+
+/// Identifier of an element: its index in the [`ElementRegistry`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Element(pub u16);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+pub enum Kind {
+    /// Immovable voxels: walls and terrain.
+    Static,
+    Solid,
+    Liquid,
+    Gas,
+}
+
+/// Description of an element, as written in the element definition file.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct ElementDefinition {
+    pub name: String,
+    /// sRGB color, each channel between 0 and 1.
+    pub color: (f32, f32, f32),
+    /// Shades of the color, each voxel is drawn with one of them picked when it
+    /// spawns. Voxels use `color` alone when empty.
+    #[serde(default)]
+    pub palette: Vec<(f32, f32, f32)>,
+    pub kind: Kind,
+    /// Relative weight: voxels sink through lighter liquids and gases and rise
+    /// through heavier ones.
+    pub density: f32,
+    /// Terminal velocity: falling voxels speed up to this many cells per tick.
+    #[serde(default = "default_speed")]
+    pub speed: f32,
+    /// Number of cells travelled per tick by sideways moves. Liquids also level
+    /// their surface through up to `dispersion` cells of their body.
+    #[serde(default = "default_dispersion")]
+    pub dispersion: usize,
+    /// Number of ticks before a voxel of this element disappears, forever if unset.
+    #[serde(default)]
+    pub lifetime: Option<u32>,
+    /// Cells the element tries to move to, relative to its position and in order
+    /// of preference.
+    pub moves: Vec<(isize, isize)>,
+    /// Temperature of new voxels, in °C.
+    #[serde(default = "default_temperature")]
+    pub temperature: f32,
+    /// Share of the temperature difference exchanged with a neighbour per tick,
+    /// between 0 (insulator) and 1.
+    #[serde(default)]
+    pub conductivity: f32,
+    /// Element the voxel turns into below the given temperature.
+    #[serde(default)]
+    pub cools_into: Option<(f32, String)>,
+    /// Element the voxel turns into above the given temperature.
+    #[serde(default)]
+    pub heats_into: Option<(f32, String)>,
+    #[serde(default)]
+    pub reactions: Vec<ReactionDefinition>,
+    /// Chance per tick of catching fire next to a voxel hotter than
+    /// [`IGNITION_TEMPERATURE`], between 0 (fireproof) and 1.
+    #[serde(default)]
+    pub flammability: f32,
+    /// Number of ticks a voxel burns before being consumed.
+    #[serde(default = "default_burn_time")]
+    pub burn_time: u32,
+    /// Element released above the voxel while it burns, when it releases nothing
+    /// else, nothing if unset.
+    #[serde(default)]
+    pub flames: Option<String>,
+    /// Element released above the voxel while it burns and left in its place once
+    /// consumed, nothing if unset.
+    #[serde(default)]
+    pub burns_into: Option<String>,
+}
+
+impl ElementDefinition {
+    /// Farthest cell a voxel of the element reaches in a tick, in cells: it takes up
+    /// to `speed` steps through its farthest move, its `dispersion` or as deep when
+    /// leveling, then splashes up to half its speed.
+    pub fn reach(&self) -> usize {
+        if self.kind == Kind::Static {
+            return 1;
+        }
+        let steps = (self.speed as usize).max(1);
+        let step = self
+            .moves
+            .iter()
+            .map(|(dx, dy)| dx.unsigned_abs().max(dy.unsigned_abs()))
+            .chain([self.dispersion, 1])
+            .max()
+            .unwrap_or(1);
+        steps * step + (self.speed / 2.) as usize
+    }
+}
+
+/// Reaction of an element with a neighbouring element, as written in the element
+/// definition file: `water + lava -> steam + stone`.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct ReactionDefinition {
+    pub with: String,
+    /// What the voxel turns into, it disappears if unset.
+    pub into: Option<String>,
+    /// What the neighbour turns into, it disappears if unset.
+    pub other_into: Option<String>,
+    /// Chance of the reaction happening each tick the two voxels touch.
+    pub probability: f32,
+}
+
+/// A [`ReactionDefinition`] with the element names resolved.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Reaction {
+    pub with: Element,
+    pub into: Option<Element>,
+    pub other_into: Option<Element>,
+    pub probability: f32,
+}
+
+fn default_speed() -> f32 {
+    1.
+}
+
+fn default_dispersion() -> usize {
+    1
+}
+
+fn default_burn_time() -> u32 {
+    60
+}
+
+fn default_temperature() -> f32 {
+    AMBIENT_TEMPERATURE
+}
+
+/// Phase changes of an element, with the element names resolved.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct PhaseChanges {
+    pub cools_into: Option<(f32, Element)>,
+    pub heats_into: Option<(f32, Element)>,
+}
+
+#[derive(Debug)]
+pub enum RegistryError {
+    Parse(ron::error::SpannedError),
+    /// An element refers to an element that doesn't exist.
+    UnknownElement {
+        name: String,
+        referenced_by: String,
+    },
+    /// An element reaches farther in a tick than a chunk update may, see
+    /// [`ElementDefinition::reach`].
+    OutOfReach {
+        name: String,
+        reach: usize,
+    },
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryError::Parse(e) => write!(f, "{}", e),
+            RegistryError::UnknownElement {
+                name,
+                referenced_by,
+            } => write!(f, "unknown element {:?} used by {:?}", name, referenced_by),
+            RegistryError::OutOfReach { name, reach } => write!(
+                f,
+                "{:?} reaches {} cells per tick, more than {}",
+                name, reach, TILE_MARGIN
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+impl From<ron::error::SpannedError> for RegistryError {
+    fn from(e: ron::error::SpannedError) -> Self {
+        RegistryError::Parse(e)
+    }
+}
+
+/// All the elements of a world, loaded from a RON definition file.
+#[derive(Clone, Debug)]
+pub struct ElementRegistry {
+    elements: Vec<ElementDefinition>,
+    phase_changes: Vec<PhaseChanges>,
+    reactions: Vec<Vec<Reaction>>,
+    flames: Vec<Option<Element>>,
+    burns_into: Vec<Option<Element>>,
+}
+
+impl Default for ElementRegistry {
+    fn default() -> Self {
+        ElementRegistry::from_ron(DEFAULT_ELEMENTS).expect("default elements should be valid")
+    }
+}
+
+impl ElementRegistry {
+    pub fn new(elements: Vec<ElementDefinition>) -> Result<Self, RegistryError> {
+        if let Some(definition) = elements.iter().find(|d| d.reach() > TILE_MARGIN) {
+            return Err(RegistryError::OutOfReach {
+                name: definition.name.clone(),
+                reach: definition.reach(),
+            });
+        }
+        let mut registry = ElementRegistry {
+            elements,
+            phase_changes: Vec::new(),
+            reactions: Vec::new(),
+            flames: Vec::new(),
+            burns_into: Vec::new(),
+        };
+        registry.phase_changes = registry
+            .elements
+            .iter()
+            .map(|definition| {
+                Ok(PhaseChanges {
+                    cools_into: registry.resolve(definition, &definition.cools_into)?,
+                    heats_into: registry.resolve(definition, &definition.heats_into)?,
+                })
+            })
+            .collect::<Result<_, RegistryError>>()?;
+        registry.reactions = registry
+            .elements
+            .iter()
+            .map(|definition| {
+                definition
+                    .reactions
+                    .iter()
+                    .map(|reaction| {
+                        Ok(Reaction {
+                            with: registry.find_referenced(definition, &reaction.with)?,
+                            into: registry.find_optional(definition, &reaction.into)?,
+                            other_into: registry.find_optional(definition, &reaction.other_into)?,
+                            probability: reaction.probability,
+                        })
+                    })
+                    .collect()
+            })
+            .collect::<Result<_, RegistryError>>()?;
+        registry.flames = registry
+            .elements
+            .iter()
+            .map(|definition| registry.find_optional(definition, &definition.flames))
+            .collect::<Result<_, RegistryError>>()?;
+        registry.burns_into = registry
+            .elements
+            .iter()
+            .map(|definition| registry.find_optional(definition, &definition.burns_into))
+            .collect::<Result<_, RegistryError>>()?;
+        Ok(registry)
+    }
+
+    pub fn from_ron(source: &str) -> Result<Self, RegistryError> {
+        ElementRegistry::new(ron::from_str(source)?)
+    }
+
+    /// Panics if `element` doesn't come from this registry.
+    pub fn get(&self, element: Element) -> &ElementDefinition {
+        &self.elements[element.0 as usize]
+    }
+
+    pub fn phase_changes(&self, element: Element) -> PhaseChanges {
+        self.phase_changes[element.0 as usize]
+    }
+
+    /// Reactions of `element` with its neighbours, in order of priority.
+    pub fn reactions(&self, element: Element) -> &[Reaction] {
+        &self.reactions[element.0 as usize]
+    }
+
+    /// Flames rising from a burning voxel of `element`.
+    pub fn flames(&self, element: Element) -> Option<Element> {
+        self.flames[element.0 as usize]
+    }
+
+    /// What a burning voxel of `element` releases and leaves behind.
+    pub fn burns_into(&self, element: Element) -> Option<Element> {
+        self.burns_into[element.0 as usize]
+    }
+
+    pub fn find(&self, name: &str) -> Option<Element> {
+        self.elements
+            .iter()
+            .position(|definition| definition.name == name)
+            .map(|index| Element(index as u16))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Element, &ElementDefinition)> {
+        self.elements
+            .iter()
+            .enumerate()
+            .map(|(index, definition)| (Element(index as u16), definition))
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    pub fn spawn_voxel(&self, element: Element) -> Voxel {
+        Voxel::of {
+            data: VoxelStruct {
+                velocity: 0.,
+                element,
+                lifetime: self.get(element).lifetime,
+                temperature: self.get(element).temperature,
+                burning: false,
+                shade: 0,
+            },
+        }
+    }
+
+    /// Spawns a voxel with a random shade of its element palette.
+    pub fn spawn_shaded_voxel(&self, element: Element, rng: &mut Rng) -> Voxel {
+        let mut voxel = self.spawn_voxel(element);
+        if let Voxel::of { data } = &mut voxel {
+            data.shade = rng.below(u8::MAX as usize + 1) as u8;
+        }
+        voxel
+    }
+
+    /// Color of a voxel of the element with the given shade.
+    pub fn shade_color(&self, element: Element, shade: u8) -> (f32, f32, f32) {
+        let definition = self.get(element);
+        if definition.palette.is_empty() {
+            definition.color
+        } else {
+            definition.palette[shade as usize % definition.palette.len()]
+        }
+    }
+
+    fn resolve(
+        &self,
+        definition: &ElementDefinition,
+        phase_change: &Option<(f32, String)>,
+    ) -> Result<Option<(f32, Element)>, RegistryError> {
+        match phase_change {
+            Some((temperature, name)) => Ok(Some((
+                *temperature,
+                self.find_referenced(definition, name)?,
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    fn find_optional(
+        &self,
+        definition: &ElementDefinition,
+        name: &Option<String>,
+    ) -> Result<Option<Element>, RegistryError> {
+        name.as_ref()
+            .map(|name| self.find_referenced(definition, name))
+            .transpose()
+    }
+
+    /// Finds the element called `name`, used in the definition of another element.
+    fn find_referenced(
+        &self,
+        definition: &ElementDefinition,
+        name: &str,
+    ) -> Result<Element, RegistryError> {
+        self.find(name)
+            .ok_or_else(|| RegistryError::UnknownElement {
+                name: name.to_string(),
+                referenced_by: definition.name.clone(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_elements_are_loaded_in_order() {
+        let registry = ElementRegistry::default();
+
+        let names: Vec<_> = registry.iter().map(|(_, d)| d.name.as_str()).collect();
+        assert_eq!(names[..3], ["sand", "water", "earth"]);
+        assert_eq!(registry.find("water"), Some(Element(1)));
+        assert_eq!(registry.get(Element(1)).kind, Kind::Liquid);
+    }
+
+    #[test]
+    fn phase_changes_refer_to_existing_elements() {
+        let registry = ElementRegistry::default();
+        let water = registry.find("water").unwrap();
+
+        assert_eq!(
+            registry.phase_changes(water).heats_into,
+            Some((100., registry.find("steam").unwrap()))
+        );
+        let error = ElementRegistry::from_ron(
+            r#"[(name: "water", color: (0., 0., 1.), kind: Liquid, density: 1., moves: [],
+                 cools_into: Some((0., "ice")))]"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "unknown element \"ice\" used by \"water\""
+        );
+    }
+
+    #[test]
+    fn elements_stay_within_a_chunk_update() {
+        let registry = ElementRegistry::default();
+        let water = registry.get(registry.find("water").unwrap());
+
+        assert_eq!(water.reach(), 16);
+        let error = ElementRegistry::from_ron(
+            r#"[(name: "mercury", color: (0.8, 0.8, 0.8), kind: Liquid, density: 13.,
+                 speed: 4., dispersion: 4, moves: [(0, -1)])]"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "\"mercury\" reaches 18 cells per tick, more than 16"
+        );
+    }
+
+    #[test]
+    fn elements_can_be_added_without_code() {
+        let registry = ElementRegistry::from_ron(
+            r#"[(
+                name: "gravel",
+                color: (0.5, 0.5, 0.5),
+                kind: Solid,
+                density: 2.0,
+                speed: 2.0,
+                moves: [(0, -1)],
+            )]"#,
+        )
+        .unwrap();
+
+        let gravel = registry.find("gravel").unwrap();
+        assert_eq!(
+            registry.spawn_voxel(gravel),
+            Voxel::of {
+                data: VoxelStruct {
+                    velocity: 0.,
+                    element: gravel,
+                    lifetime: None,
+                    temperature: AMBIENT_TEMPERATURE,
+                    burning: false,
+                    shade: 0,
+                }
+            }
+        );
+    }
+}
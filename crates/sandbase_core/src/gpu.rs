@@ -0,0 +1,941 @@
+use std::borrow::Cow;
+use std::convert::TryInto;
+use std::fmt;
+use std::sync::mpsc;
+
+use crate::backend::{SimulationBackend, SimulationError};
+use crate::chunk::CHUNK_SIZE;
+use crate::elements::{Element, ElementRegistry, Kind, BURN_TEMPERATURE, IGNITION_TEMPERATURE};
+use crate::map::{GameMap, AIR_CONDUCTIVITY, FLAME_CHANCE, GRAVITY, SMOKE_CHANCE, SPLASH_VELOCITY};
+use crate::voxels::VoxelStruct;
+
+/// Compute shader of the element rules.
+const SHADER: &str = include_str!("../../../assets/shaders/automata.wgsl");
+
+/// Entry points of the shader, in the order a tick runs them.
+const PASSES: [&str; 4] = ["choose", "resolve", "apply", "conduct"];
+
+/// Cells along each axis of a workgroup.
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Moves and reactions of an element the shader has room for.
+const MAX_MOVES: usize = 8;
+const MAX_REACTIONS: usize = 4;
+
+/// Bytes of a cell, action, rule and parameter block, as laid out in the shader.
+const CELL_SIZE: u64 = 20;
+const ACTION_SIZE: u64 = 36;
+const RULE_SIZE: u64 = 208;
+const PARAMS_SIZE: u64 = 64;
+
+/// Lifetime of the voxels living forever.
+const NO_LIFETIME: u32 = u32::MAX;
+const BURNING: u32 = 1;
+
+/// Storage buffers bound by the shader.
+const STORAGE_BUFFERS: u32 = 6;
+
+#[derive(Debug)]
+pub enum GpuError {
+    /// No adapter is available, not even a software one.
+    NoAdapter,
+    /// The adapter can't run the shader, such as WebGL2 without compute shaders.
+    Unsupported(String),
+    Device(wgpu::RequestDeviceError),
+}
+
+impl fmt::Display for GpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GpuError::NoAdapter => write!(f, "no GPU adapter available"),
+            GpuError::Unsupported(adapter) => {
+                write!(f, "{} can't run the simulation compute shaders", adapter)
+            }
+            GpuError::Device(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for GpuError {}
+
+impl From<wgpu::RequestDeviceError> for GpuError {
+    fn from(e: wgpu::RequestDeviceError) -> Self {
+        GpuError::Device(e)
+    }
+}
+
+/// Runs the element rules in wgpu compute shaders, several ticks per upload of the
+/// map. The cells are only read back once the ticks ran.
+///
+/// Voxels update in parallel, each one like [`GameMap::step`] would update it on
+/// the map as it was at the start of the tick, with the same random numbers: it
+/// burns, ages, then walks up to its velocity in cells or reacts with or sets fire
+/// to a neighbour. When several voxels act on the same cell, the first one the CPU
+/// would have updated wins, the others stay where they are. Heat and phase changes
+/// follow once every voxel acted. As voxels the CPU updates one after the other
+/// see each other's moves, the two backends agree on lone voxels and on where
+/// crowds settle rather than on every tick.
+pub struct GpuBackend {
+    adapter_name: String,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipelines: Vec<wgpu::ComputePipeline>,
+    buffers: Option<Buffers>,
+}
+
+/// Buffers of a map size and element count, kept from one run to the next.
+struct Buffers {
+    width: usize,
+    height: usize,
+    elements: usize,
+    params: wgpu::Buffer,
+    rules: wgpu::Buffer,
+    cells: wgpu::Buffer,
+    moved: wgpu::Buffer,
+    /// Cells followed by the moved counter, read back by the CPU.
+    staging: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl GpuBackend {
+    /// Opens the first adapter able to run compute shaders. The `WGPU_BACKEND`
+    /// environment variable restricts the graphics APIs tried, e.g. `vulkan`.
+    pub fn new() -> Result<Self, GpuError> {
+        let backends = wgpu::util::backend_bits_from_env().unwrap_or_else(wgpu::Backends::all);
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends,
+            ..Default::default()
+        });
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter: false,
+            compatible_surface: None,
+        }))
+        .ok_or(GpuError::NoAdapter)?;
+        let adapter_name = adapter.get_info().name;
+        let limits = adapter.limits();
+        if !adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+            || limits.max_storage_buffers_per_shader_stage < STORAGE_BUFFERS
+        {
+            return Err(GpuError::Unsupported(adapter_name));
+        }
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("simulation"),
+                features: wgpu::Features::empty(),
+                limits,
+            },
+            None,
+        ))?;
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("automata"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(SHADER)),
+        });
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("simulation"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1, true),
+                storage(2, false),
+                storage(3, false),
+                storage(4, false),
+                storage(5, false),
+                storage(6, false),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("simulation"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipelines = PASSES
+            .iter()
+            .map(|entry_point| {
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some(entry_point),
+                    layout: Some(&pipeline_layout),
+                    module: &shader,
+                    entry_point,
+                })
+            })
+            .collect();
+
+        Ok(GpuBackend {
+            adapter_name,
+            device,
+            queue,
+            bind_group_layout,
+            pipelines,
+            buffers: None,
+        })
+    }
+
+    /// Name of the adapter running the shaders.
+    pub fn adapter_name(&self) -> &str {
+        &self.adapter_name
+    }
+
+    /// Checks that the shader has room for the rules of the elements of `registry`.
+    pub fn check_rules(registry: &ElementRegistry) -> Result<(), SimulationError> {
+        for (element, definition) in registry.iter() {
+            let rule = if definition.moves.len() > MAX_MOVES {
+                "more than 8 moves"
+            } else if registry.reactions(element).len() > MAX_REACTIONS {
+                "more than 4 reactions"
+            } else {
+                continue;
+            };
+            return Err(SimulationError::UnsupportedElement {
+                element: definition.name.clone(),
+                rule,
+            });
+        }
+        Ok(())
+    }
+
+    /// Creates the buffers for `map` again when its size or elements changed.
+    fn prepare_buffers(&mut self, map: &GameMap) {
+        let size = (map.width, map.height, map.registry().len());
+        let reusable = self
+            .buffers
+            .as_ref()
+            .is_some_and(|buffers| (buffers.width, buffers.height, buffers.elements) == size);
+        if !reusable {
+            let (width, height, elements) = size;
+            self.buffers = Some(self.create_buffers(width, height, elements));
+        }
+    }
+
+    fn create_buffers(&self, width: usize, height: usize, elements: usize) -> Buffers {
+        let cells_size = (width * height) as u64 * CELL_SIZE;
+        let buffer = |label, size, usage| {
+            self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        let storage = wgpu::BufferUsages::STORAGE;
+        let params = buffer(
+            "params",
+            PARAMS_SIZE,
+            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        );
+        let rules = buffer(
+            "rules",
+            elements.max(1) as u64 * RULE_SIZE,
+            storage | wgpu::BufferUsages::COPY_DST,
+        );
+        let cells = buffer(
+            "cells",
+            cells_size,
+            storage | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+        );
+        let moved_cells = buffer("moved cells", cells_size, storage);
+        let actions = buffer("actions", (width * height) as u64 * ACTION_SIZE, storage);
+        // Cleared by the shader at the end of each tick, new buffers start zeroed
+        let claims = buffer("claims", (width * height) as u64 * 4, storage);
+        let moved = buffer(
+            "moved",
+            4,
+            storage | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+        );
+        let staging = buffer(
+            "staging",
+            cells_size + 4,
+            wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        );
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("simulation"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                &params,
+                &rules,
+                &cells,
+                &moved_cells,
+                &actions,
+                &claims,
+                &moved,
+            ]
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect::<Vec<_>>(),
+        });
+        Buffers {
+            width,
+            height,
+            elements,
+            params,
+            rules,
+            cells,
+            moved,
+            staging,
+            bind_group,
+        }
+    }
+}
+
+impl SimulationBackend for GpuBackend {
+    fn name(&self) -> &str {
+        "gpu"
+    }
+
+    fn run(&mut self, map: &mut GameMap, ticks: usize) -> Result<usize, SimulationError> {
+        GpuBackend::check_rules(map.registry())?;
+        if ticks == 0 || map.width == 0 || map.height == 0 {
+            return Ok(0);
+        }
+        self.prepare_buffers(map);
+        let buffers = self.buffers.as_ref().unwrap();
+        let (device, queue) = (&self.device, &self.queue);
+        queue.write_buffer(&buffers.rules, 0, &encode_rules(map.registry()));
+        queue.write_buffer(&buffers.cells, 0, &encode_cells(map.cells()));
+        queue.write_buffer(&buffers.moved, 0, &0u32.to_le_bytes());
+
+        let workgroups = |cells: usize| (cells as u32).div_ceil(WORKGROUP_SIZE);
+        for tick in 0..ticks {
+            // Parameters are written when the commands are submitted, one submission
+            // per tick keeps each tick's parameters
+            queue.write_buffer(&buffers.params, 0, &encode_params(map, tick as u64));
+            let mut encoder = device.create_command_encoder(&Default::default());
+            for pipeline in &self.pipelines {
+                let mut pass = encoder.begin_compute_pass(&Default::default());
+                pass.set_pipeline(pipeline);
+                pass.set_bind_group(0, &buffers.bind_group, &[]);
+                pass.dispatch_workgroups(workgroups(map.width), workgroups(map.height), 1);
+            }
+            queue.submit(Some(encoder.finish()));
+        }
+
+        let cells_size = buffers.cells.size();
+        let mut encoder = device.create_command_encoder(&Default::default());
+        encoder.copy_buffer_to_buffer(&buffers.cells, 0, &buffers.staging, 0, cells_size);
+        encoder.copy_buffer_to_buffer(&buffers.moved, 0, &buffers.staging, cells_size, 4);
+        queue.submit(Some(encoder.finish()));
+
+        let slice = buffers.staging.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        match receiver.recv() {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => return Err(SimulationError::Device(e.to_string())),
+            Err(_) => {
+                return Err(SimulationError::Device(
+                    "the GPU never returned the cells".to_string(),
+                ))
+            }
+        }
+        let (cells, moved) = {
+            let bytes = slice.get_mapped_range();
+            let (cells, moved) = bytes.split_at(cells_size as usize);
+            (decode_cells(cells), read_u32(moved, 0))
+        };
+        buffers.staging.unmap();
+
+        map.advance_to(ticks as u64, cells);
+        Ok(moved as usize)
+    }
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Pushes the low then the high half, as the shader reads them.
+fn push_u64(bytes: &mut Vec<u8>, value: u64) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_f32(bytes: &mut Vec<u8>, value: f32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn encode_params(map: &GameMap, tick: u64) -> Vec<u8> {
+    let tick = map.tick() + tick;
+    let mut bytes = Vec::with_capacity(PARAMS_SIZE as usize);
+    push_u32(&mut bytes, map.width as u32);
+    push_u32(&mut bytes, map.height as u32);
+    push_u64(&mut bytes, tick);
+    push_u64(&mut bytes, map.seed);
+    push_f32(&mut bytes, map.ambient_temperature);
+    push_f32(&mut bytes, AIR_CONDUCTIVITY);
+    push_f32(&mut bytes, GRAVITY);
+    push_f32(&mut bytes, SPLASH_VELOCITY);
+    push_f32(&mut bytes, IGNITION_TEMPERATURE);
+    push_f32(&mut bytes, BURN_TEMPERATURE);
+    push_f32(&mut bytes, SMOKE_CHANCE);
+    push_f32(&mut bytes, FLAME_CHANCE);
+    push_u32(&mut bytes, CHUNK_SIZE as u32);
+    bytes.resize(PARAMS_SIZE as usize, 0);
+    bytes
+}
+
+fn encode_rules(registry: &ElementRegistry) -> Vec<u8> {
+    // Elements are numbered from 1, 0 stands for none
+    let element_id = |element: Option<Element>| element.map_or(0, |element| element.0 as u32 + 1);
+    let phase_change = |change: Option<(f32, Element)>| match change {
+        Some((threshold, element)) => (threshold, element_id(Some(element))),
+        None => (0., 0),
+    };
+    let mut bytes = Vec::with_capacity(registry.len() * RULE_SIZE as usize);
+    for (element, definition) in registry.iter() {
+        for index in 0..MAX_MOVES {
+            let (dx, dy) = definition.moves.get(index).copied().unwrap_or((0, 0));
+            push_u32(&mut bytes, dx as i32 as u32);
+            push_u32(&mut bytes, dy as i32 as u32);
+        }
+        let reactions = registry.reactions(element);
+        for index in 0..MAX_REACTIONS {
+            match reactions.get(index) {
+                Some(reaction) => {
+                    push_u32(&mut bytes, element_id(Some(reaction.with)));
+                    push_u32(&mut bytes, element_id(reaction.into));
+                    push_u32(&mut bytes, element_id(reaction.other_into));
+                    push_f32(&mut bytes, reaction.probability);
+                }
+                None => bytes.extend_from_slice(&[0; 16]),
+            }
+        }
+        let kind = match definition.kind {
+            Kind::Static => 0,
+            Kind::Solid => 1,
+            Kind::Liquid => 2,
+            Kind::Gas => 3,
+        };
+        let phase_changes = registry.phase_changes(element);
+        let (cools_below, cools_into) = phase_change(phase_changes.cools_into);
+        let (heats_above, heats_into) = phase_change(phase_changes.heats_into);
+        push_u32(&mut bytes, kind);
+        push_f32(&mut bytes, definition.density);
+        push_u32(&mut bytes, definition.dispersion as u32);
+        push_u32(&mut bytes, definition.moves.len().min(MAX_MOVES) as u32);
+        push_f32(&mut bytes, definition.conductivity);
+        push_f32(&mut bytes, cools_below);
+        push_u32(&mut bytes, cools_into);
+        push_f32(&mut bytes, heats_above);
+        push_u32(&mut bytes, heats_into);
+        push_u32(&mut bytes, definition.lifetime.unwrap_or(NO_LIFETIME));
+        push_u32(&mut bytes, reactions.len().min(MAX_REACTIONS) as u32);
+        push_f32(&mut bytes, definition.speed);
+        push_f32(&mut bytes, definition.temperature);
+        push_f32(&mut bytes, definition.flammability);
+        push_u32(&mut bytes, definition.burn_time);
+        push_u32(&mut bytes, element_id(registry.burns_into(element)));
+        push_u32(&mut bytes, element_id(registry.flames(element)));
+        bytes.resize(bytes.len() + 12, 0);
+    }
+    bytes
+}
+
+fn encode_cells(cells: &[Option<VoxelStruct>]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(cells.len() * CELL_SIZE as usize);
+    for cell in cells {
+        match cell {
+            Some(data) => {
+                push_u32(&mut bytes, data.element.0 as u32 + 1);
+                push_f32(&mut bytes, data.temperature);
+                push_u32(&mut bytes, data.lifetime.unwrap_or(NO_LIFETIME));
+                push_u32(&mut bytes, data.burning as u32 | (data.shade as u32) << 8);
+                push_f32(&mut bytes, data.velocity);
+            }
+            None => bytes.extend_from_slice(&[0; CELL_SIZE as usize]),
+        }
+    }
+    bytes
+}
+
+fn decode_cells(bytes: &[u8]) -> Vec<Option<VoxelStruct>> {
+    bytes
+        .chunks_exact(CELL_SIZE as usize)
+        .map(|cell| match read_u32(cell, 0) {
+            0 => None,
+            element => {
+                let flags = read_u32(cell, 12);
+                Some(VoxelStruct {
+                    velocity: f32::from_bits(read_u32(cell, 16)),
+                    element: Element(element as u16 - 1),
+                    lifetime: Some(read_u32(cell, 8)).filter(|lifetime| *lifetime != NO_LIFETIME),
+                    temperature: f32::from_bits(read_u32(cell, 4)),
+                    burning: flags & BURNING != 0,
+                    shade: (flags >> 8) as u8,
+                })
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::backend::CpuBackend;
+    use crate::rng::Rng;
+    use crate::voxels::Voxel;
+    use crate::world_position::WorldPosition;
+
+    // The rules are compared with the CPU backend one group at a time: moves and
+    // densities in shafts, leveling in vessels, gas moves and sideways dispersion
+    // with voxels that never compete for a cell, then lifetimes, heat and phase
+    // changes, and the default elements falling, reacting and burning on their
+    // own. Only voxel conservation is checked on a crowded map, where the two
+    // backends settle the same voxels in different places.
+
+    /// Elements following the rules the shader simulates.
+    const ELEMENTS: &str = r#"[
+        (name: "sand", color: (1., 1., 0.), kind: Solid, density: 1.6, conductivity: 0.2,
+         moves: [(0, -1), (-1, -1), (1, -1)]),
+        (name: "water", color: (0., 0., 1.), kind: Liquid, density: 1., dispersion: 4,
+         conductivity: 0.6, cools_into: Some((0., "ice")), heats_into: Some((100., "steam")),
+         moves: [(0, -1), (-1, -1), (1, -1), (-1, 0), (1, 0)]),
+        (name: "oil", color: (0.2, 0.2, 0.), kind: Liquid, density: 0.8, dispersion: 2,
+         conductivity: 0.15, moves: [(0, -1), (-1, -1), (1, -1), (-1, 0), (1, 0)]),
+        (name: "steam", color: (1., 1., 1.), kind: Gas, density: 0.01, dispersion: 2,
+         lifetime: Some(300), temperature: 110., conductivity: 0.05,
+         cools_into: Some((40., "water")),
+         moves: [(0, 1), (-1, 1), (1, 1), (-1, 0), (1, 0)]),
+        (name: "stone", color: (0.5, 0.5, 0.5), kind: Static, density: 2.5, conductivity: 0.3,
+         moves: []),
+        (name: "metal", color: (0.6, 0.6, 0.7), kind: Static, density: 7.8, conductivity: 0.9,
+         moves: []),
+        (name: "ice", color: (0.8, 0.9, 1.), kind: Static, density: 0.92, temperature: -10.,
+         conductivity: 0.5, heats_into: Some((0., "water")), moves: []),
+    ]"#;
+
+    fn map(width: usize, height: usize) -> GameMap {
+        let registry = ElementRegistry::from_ron(ELEMENTS).unwrap();
+        GameMap::with_registry(width, height, registry)
+    }
+
+    fn voxel(map: &GameMap, name: &str) -> Voxel {
+        map.registry()
+            .spawn_voxel(map.registry().find(name).unwrap())
+    }
+
+    /// The GPU backend, `None` when the machine has no adapter able to run it. CI
+    /// and runs picking an adapter with `WGPU_BACKEND` expect one, they fail instead
+    /// of skipping the GPU tests.
+    fn gpu() -> Option<GpuBackend> {
+        match GpuBackend::new() {
+            Ok(backend) => Some(backend),
+            Err(e) if env::var_os("WGPU_BACKEND").is_some() || env::var_os("CI").is_some() => {
+                panic!("no GPU adapter to test with: {}", e)
+            }
+            Err(e) => {
+                eprintln!("skipping GPU test: {}", e);
+                None
+            }
+        }
+    }
+
+    fn elements(map: &GameMap) -> Vec<Option<String>> {
+        map.cells()
+            .iter()
+            .map(|cell| cell.map(|data| map.registry().get(data.element).name.clone()))
+            .collect()
+    }
+
+    /// Asserts that the two maps hold the same voxels. Temperatures are left out:
+    /// the CPU only exchanges heat in the chunks' dirty rects, which lag behind
+    /// fast voxels, see `heat_spreads_like_on_the_cpu` for heat.
+    fn assert_same_voxels(gpu_map: &GameMap, cpu_map: &GameMap, tick: usize) {
+        let without_temperature = |cell: &Option<VoxelStruct>| {
+            cell.map(|data| VoxelStruct {
+                temperature: 0.,
+                ..data
+            })
+        };
+        let differences = gpu_map
+            .cells()
+            .iter()
+            .zip(cpu_map.cells())
+            .enumerate()
+            .filter(|(_, (gpu_cell, cpu_cell))| {
+                without_temperature(gpu_cell) != without_temperature(cpu_cell)
+            })
+            .map(|(index, cells)| (gpu_map.position(index), cells))
+            .collect::<Vec<_>>();
+        assert!(
+            differences.is_empty(),
+            "cells differing at tick {}: {:?}",
+            tick,
+            differences
+        );
+    }
+
+    #[test]
+    fn elements_the_shader_has_no_room_for_are_refused() {
+        let crowded = ElementRegistry::from_ron(
+            r#"[(name: "sand", color: (1., 1., 0.), kind: Solid, density: 1.6,
+                 moves: [(0, -1), (0, -1), (0, -1), (0, -1), (0, -1), (0, -1), (0, -1),
+                         (0, -1), (0, -1)])]"#,
+        )
+        .unwrap();
+
+        assert!(GpuBackend::check_rules(&ElementRegistry::default()).is_ok());
+        assert!(GpuBackend::check_rules(&ElementRegistry::from_ron(ELEMENTS).unwrap()).is_ok());
+        assert!(matches!(
+            GpuBackend::check_rules(&crowded),
+            Err(SimulationError::UnsupportedElement { .. })
+        ));
+    }
+
+    #[test]
+    fn maps_with_unsupported_elements_are_not_stepped() {
+        let mut gpu = match gpu() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        let registry = ElementRegistry::from_ron(
+            r#"[(name: "sand", color: (1., 1., 0.), kind: Solid, density: 1.6,
+                 moves: [(0, -1), (0, -1), (0, -1), (0, -1), (0, -1), (0, -1), (0, -1),
+                         (0, -1), (0, -1)])]"#,
+        )
+        .unwrap();
+        let mut map = GameMap::with_registry(4, 4, registry);
+        map.set_cell(&WorldPosition::new(1, 3), &voxel(&map, "sand"));
+        let cells = map.cells().to_vec();
+
+        let error = gpu.run(&mut map, 5).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "\"sand\" uses more than 8 moves, which the backend can't simulate"
+        );
+        assert_eq!(map.tick(), 0);
+        assert_eq!(map.cells(), &cells[..]);
+    }
+
+    #[test]
+    fn default_elements_fall_like_on_the_cpu() {
+        let mut gpu = match gpu() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        let registry = ElementRegistry::default();
+        for (element, definition) in registry.iter() {
+            // Lone voxels speed up, splash on the floor or rise, age and cool down
+            let mut map = GameMap::new(16, 30);
+            map.seed = 11;
+            let mut voxel = registry.spawn_voxel(element);
+            if let Voxel::of { data } = &mut voxel {
+                data.shade = 7;
+            }
+            map.set_cell(&WorldPosition::new(8, 15), &voxel);
+            let mut cpu_map = map.clone();
+
+            // Until the lava cools down into stone, a tick later on the CPU which
+            // only exchanges heat in the chunks' dirty rects
+            for tick in 0..32 {
+                let moved = gpu.run(&mut map, 1).unwrap();
+                let cpu_moved = CpuBackend.run(&mut cpu_map, 1).unwrap();
+                assert_eq!(moved, cpu_moved, "{} at tick {}", definition.name, tick);
+                assert_same_voxels(&map, &cpu_map, tick);
+            }
+        }
+    }
+
+    #[test]
+    fn water_reacts_with_lava_like_on_the_cpu() {
+        let mut gpu = match gpu() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        let registry = ElementRegistry::default();
+        let spawn = |name| registry.spawn_voxel(registry.find(name).unwrap());
+        let stone = registry.find("stone").unwrap();
+        let mut reactions = 0;
+        for seed in 0..8 {
+            // A drop on a lava floor, that reacts or boils, below a ceiling high
+            // enough for the steam it makes to never reach it
+            let mut map = GameMap::new(24, 40);
+            map.seed = seed;
+            for x in 0..map.width {
+                map.set_cell(&WorldPosition::new(x, 0), &spawn("lava"));
+            }
+            map.set_cell(&WorldPosition::new(12, 1), &spawn("water"));
+            let mut cpu_map = map.clone();
+
+            for tick in 0..30 {
+                gpu.run(&mut map, 1).unwrap();
+                CpuBackend.run(&mut cpu_map, 1).unwrap();
+                assert_same_voxels(&map, &cpu_map, tick);
+            }
+            // The lava only turns into stone when reacting
+            reactions += map.iter().filter(|(_, data)| data.element == stone).count();
+        }
+        assert!(reactions > 0);
+    }
+
+    #[test]
+    fn fire_spreads_like_on_the_cpu() {
+        let mut gpu = match gpu() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        let mut map = GameMap::new(12, 16);
+        map.seed = 9;
+        let registry = map.registry().clone();
+        let (wood, lava) = (
+            registry.find("wood").unwrap(),
+            registry.find("lava").unwrap(),
+        );
+        for x in 0..map.width {
+            map.set_cell(&WorldPosition::new(x, 0), &registry.spawn_voxel(wood));
+        }
+        map.set_cell(&WorldPosition::new(6, 1), &registry.spawn_voxel(lava));
+        let mut cpu_map = map.clone();
+
+        // Until the flames released by the wood compete for cells
+        for tick in 0..28 {
+            gpu.run(&mut map, 1).unwrap();
+            CpuBackend.run(&mut cpu_map, 1).unwrap();
+            assert_same_voxels(&map, &cpu_map, tick);
+        }
+        let fire = registry.find("fire").unwrap();
+        assert!(map.iter().filter(|(_, data)| data.burning).count() > 1);
+        assert!(map.iter().any(|(_, data)| data.element == fire));
+    }
+
+    #[test]
+    fn shafts_settle_like_on_the_cpu() {
+        let mut gpu = match gpu() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        // Shafts one cell wide between stone walls, filled with mixed layers
+        let mut map = map(9, 16);
+        let layers = ["sand", "water", "oil", "sand", "oil", "water"];
+        for y in 0..map.height {
+            for x in (0..map.width).step_by(2) {
+                map.set_cell(&WorldPosition::new(x, y), &voxel(&map, "stone"));
+            }
+        }
+        for (shaft, x) in (1..map.width).step_by(2).enumerate() {
+            for (y, name) in layers.iter().cycle().skip(shaft).take(9).enumerate() {
+                map.set_cell(&WorldPosition::new(x, y + 4), &voxel(&map, name));
+            }
+        }
+        let mut cpu_map = map.clone();
+
+        let moved = gpu.run(&mut map, 200).unwrap();
+        CpuBackend.run(&mut cpu_map, 200).unwrap();
+
+        assert!(moved > 0);
+        assert_eq!(map.tick(), 200);
+        assert_eq!(elements(&map), elements(&cpu_map));
+        // Oil floats on water, sand at the bottom
+        let name = |y| elements(&map)[map.index(&WorldPosition::new(1, y))].clone();
+        assert_eq!(name(0).as_deref(), Some("sand"));
+        assert_eq!(name(8).as_deref(), Some("oil"));
+    }
+
+    #[test]
+    fn voxels_are_neither_created_nor_lost() {
+        let mut gpu = match gpu() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        let mut map = map(40, 30);
+        // Warm enough for steam not to condense and water not to boil
+        map.ambient_temperature = 60.;
+        let names = ["sand", "water", "oil", "steam", "stone"];
+        let mut rng = Rng::new(7);
+        for y in 0..map.height {
+            for x in 0..map.width {
+                if rng.chance(0.4) {
+                    map.set_cell(&WorldPosition::new(x, y), &voxel(&map, names[rng.below(5)]));
+                }
+            }
+        }
+        let count = |map: &GameMap| {
+            let mut names = elements(map).into_iter().flatten().collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+        let before = count(&map);
+
+        gpu.run(&mut map, 50).unwrap();
+
+        assert_eq!(count(&map), before);
+    }
+
+    #[test]
+    fn gases_take_the_same_path_as_on_the_cpu() {
+        let mut gpu = match gpu() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        let mut map = map(15, 40);
+        map.seed = 0x1234_5678_9abc_def0;
+        for x in [2, 7, 12] {
+            map.set_cell(&WorldPosition::new(x, 0), &voxel(&map, "steam"));
+        }
+        let mut cpu_map = map.clone();
+
+        // Voxels far enough apart never compete for a cell, they follow their random
+        // moves exactly
+        for _ in 0..15 {
+            gpu.run(&mut map, 1).unwrap();
+            CpuBackend.run(&mut cpu_map, 1).unwrap();
+            assert_eq!(elements(&map), elements(&cpu_map));
+        }
+        let lifetimes = |map: &GameMap| {
+            map.iter()
+                .map(|(_, data)| data.lifetime)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(lifetimes(&map), vec![Some(285); 3]);
+        assert_eq!(lifetimes(&map), lifetimes(&cpu_map));
+    }
+
+    #[test]
+    fn voxels_disappear_when_their_lifetime_runs_out() {
+        let mut gpu = match gpu() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        let mut map = map(6, 6);
+        let mut steam = voxel(&map, "steam");
+        if let Voxel::of { data } = &mut steam {
+            data.lifetime = Some(10);
+        }
+        for x in 0..map.width {
+            map.set_cell(&WorldPosition::new(x, 0), &steam);
+        }
+        let mut cpu_map = map.clone();
+
+        gpu.run(&mut map, 10).unwrap();
+        CpuBackend.run(&mut cpu_map, 10).unwrap();
+        assert_eq!(map.iter().count(), 6);
+        assert_eq!(cpu_map.iter().count(), 6);
+
+        gpu.run(&mut map, 1).unwrap();
+        CpuBackend.run(&mut cpu_map, 1).unwrap();
+        assert_eq!(map.iter().count(), 0);
+        assert_eq!(cpu_map.iter().count(), 0);
+    }
+
+    #[test]
+    fn vessels_level_like_on_the_cpu() {
+        let mut gpu = match gpu() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        // Two shafts joined at the bottom, the water of the left one spills through
+        // the body to the right one
+        let mut map = map(5, 8);
+        for y in 0..map.height {
+            for x in [0, 4] {
+                map.set_cell(&WorldPosition::new(x, y), &voxel(&map, "stone"));
+            }
+            if y > 0 {
+                map.set_cell(&WorldPosition::new(2, y), &voxel(&map, "stone"));
+            }
+        }
+        for x in 1..4 {
+            map.set_cell(&WorldPosition::new(x, 0), &voxel(&map, "water"));
+        }
+        for y in 1..5 {
+            map.set_cell(&WorldPosition::new(1, y), &voxel(&map, "water"));
+        }
+        let mut cpu_map = map.clone();
+
+        gpu.run(&mut map, 50).unwrap();
+        CpuBackend.run(&mut cpu_map, 50).unwrap();
+
+        assert_eq!(elements(&map), elements(&cpu_map));
+        let is_water = |x, y| elements(&map)[map.index(&WorldPosition::new(x, y))].is_some();
+        assert!(is_water(1, 2) && !is_water(1, 3));
+        assert!(is_water(3, 2) && !is_water(3, 3));
+    }
+
+    #[test]
+    fn heat_spreads_like_on_the_cpu() {
+        let mut gpu = match gpu() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        let mut map = map(12, 6);
+        for y in 0..map.height {
+            for x in 0..map.width {
+                let mut metal = voxel(&map, "metal");
+                if let Voxel::of { data } = &mut metal {
+                    data.temperature = if x < 3 { 300. } else { 20. };
+                }
+                map.set_cell(&WorldPosition::new(x, y), &metal);
+            }
+        }
+        let mut cpu_map = map.clone();
+
+        gpu.run(&mut map, 30).unwrap();
+        CpuBackend.run(&mut cpu_map, 30).unwrap();
+
+        for (gpu_cell, cpu_cell) in map.cells().iter().zip(cpu_map.cells()) {
+            let (gpu_cell, cpu_cell) = (gpu_cell.unwrap(), cpu_cell.unwrap());
+            assert!(
+                (gpu_cell.temperature - cpu_cell.temperature).abs() < 0.1,
+                "{} != {}",
+                gpu_cell.temperature,
+                cpu_cell.temperature
+            );
+        }
+    }
+
+    #[test]
+    fn ice_melts_above_zero() {
+        let mut gpu = match gpu() {
+            Some(gpu) => gpu,
+            None => return,
+        };
+        let mut map = map(3, 3);
+        let mut ice = voxel(&map, "ice");
+        if let Voxel::of { data } = &mut ice {
+            data.temperature = 0.5;
+        }
+        map.set_cell(&WorldPosition::new(1, 0), &ice);
+
+        gpu.run(&mut map, 1).unwrap();
+
+        assert_eq!(
+            elements(&map)[map.index(&WorldPosition::new(1, 0))].as_deref(),
+            Some("water")
+        );
+    }
+}
@@ -0,0 +1,369 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::elements::{Element, ElementRegistry, Kind};
+use crate::map::{GameMap, MAX_CELLS};
+use crate::texture::{cell_color, wet_color};
+use crate::world_position::WorldPosition;
+
+/// Pixels less opaque than this are read as empty cells.
+const OPAQUE_ALPHA: u8 = 128;
+
+/// What to do with the pixels whose color doesn't match any element.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UnknownColor {
+    /// Leave the cell empty.
+    Empty,
+    /// Use the element with the closest color, however far it is.
+    Nearest,
+    /// Stop the import with [`ImageError::UnknownColor`].
+    Fail,
+}
+
+/// How pixel colors are mapped back to elements.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ImportOptions {
+    /// Largest distance between a pixel color and an element color for the pixel to
+    /// become that element, in 8-bit channel units.
+    pub tolerance: f32,
+    pub unknown_color: UnknownColor,
+    /// Largest image accepted, width and height in pixels. Images are also refused
+    /// above a few million pixels.
+    pub max_size: Option<(usize, usize)>,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            tolerance: 24.,
+            unknown_color: UnknownColor::Empty,
+            max_size: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io(io::Error),
+    Decode(png::DecodingError),
+    Encode(png::EncodingError),
+    /// Pixel matching no element, with [`UnknownColor::Fail`]. `y` counts from the
+    /// bottom, like map positions.
+    UnknownColor {
+        x: usize,
+        y: usize,
+        color: [u8; 3],
+    },
+    /// The image is larger than [`ImportOptions::max_size`] or than any map.
+    TooLarge {
+        width: usize,
+        height: usize,
+    },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::Io(e) => write!(f, "{}", e),
+            ImageError::Decode(e) => write!(f, "{}", e),
+            ImageError::Encode(e) => write!(f, "{}", e),
+            ImageError::UnknownColor { x, y, color } => {
+                write!(f, "no element has the color {:?} of ({}, {})", color, x, y)
+            }
+            ImageError::TooLarge { width, height } => {
+                write!(f, "the image is too large ({}×{})", width, height)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+impl From<io::Error> for ImageError {
+    fn from(e: io::Error) -> Self {
+        ImageError::Io(e)
+    }
+}
+
+impl From<png::DecodingError> for ImageError {
+    fn from(e: png::DecodingError) -> Self {
+        ImageError::Decode(e)
+    }
+}
+
+impl From<png::EncodingError> for ImageError {
+    fn from(e: png::EncodingError) -> Self {
+        ImageError::Encode(e)
+    }
+}
+
+/// Writes the map as a PNG image, one pixel per cell colored like on screen, see
+/// [`cell_color`]. Empty cells are transparent.
+pub fn export_png(writer: impl Write, map: &GameMap) -> Result<(), ImageError> {
+    export_thumbnail(writer, map, 1)
+}
+
+/// Like [`export_png`], keeping one cell out of `scale` in each direction.
+pub fn export_thumbnail(writer: impl Write, map: &GameMap, scale: usize) -> Result<(), ImageError> {
+    let scale = scale.max(1);
+    let (width, height) = (map.width.div_ceil(scale), map.height.div_ceil(scale));
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    // Images go from the top row down, the map from the bottom row up
+    for row in 0..height {
+        let y = map.height - 1 - row * scale;
+        for x in (0..map.width).step_by(scale) {
+            pixels.extend_from_slice(&cell_color(map, WorldPosition::new(x, y)));
+        }
+    }
+
+    let mut encoder = png::Encoder::new(writer, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&pixels)?;
+    Ok(())
+}
+
+/// Reads a map drawn as a PNG image, one pixel per cell. Transparent pixels are
+/// empty cells, the others become the element with the closest color or shade,
+/// dry or wet for solids, so exported maps are read back.
+pub fn import_png(
+    reader: impl Read,
+    registry: ElementRegistry,
+    options: &ImportOptions,
+) -> Result<GameMap, ImageError> {
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let (width, height) = (reader.info().width as usize, reader.info().height as usize);
+    let fits = options
+        .max_size
+        .is_none_or(|(max_width, max_height)| width <= max_width && height <= max_height);
+    if !fits || width.saturating_mul(height) > MAX_CELLS {
+        return Err(ImageError::TooLarge { width, height });
+    }
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+
+    let mut palette = Vec::new();
+    for (element, definition) in registry.iter() {
+        for color in std::iter::once(&definition.color).chain(&definition.palette) {
+            palette.push((element, to_rgb8(*color)));
+            if definition.kind == Kind::Solid {
+                palette.push((element, wet_color(to_rgb8(*color))));
+            }
+        }
+    }
+    let channels = info.color_type.samples();
+    let mut map = GameMap::with_registry(width, height, registry);
+    for row in 0..height {
+        let line = &buffer[row * info.line_size..];
+        for x in 0..width {
+            let pixel = &line[x * channels..(x + 1) * channels];
+            let (color, alpha) = match pixel {
+                [gray] => ([*gray; 3], u8::MAX),
+                [gray, alpha] => ([*gray; 3], *alpha),
+                [r, g, b] => ([*r, *g, *b], u8::MAX),
+                [r, g, b, alpha, ..] => ([*r, *g, *b], *alpha),
+                [] => continue,
+            };
+            if alpha < OPAQUE_ALPHA {
+                continue;
+            }
+            let y = height - 1 - row;
+            let element = match closest_element(&palette, color) {
+                Some((element, distance)) if distance <= options.tolerance => Some(element),
+                nearest => match options.unknown_color {
+                    UnknownColor::Empty => None,
+                    UnknownColor::Nearest => nearest.map(|(element, _)| element),
+                    UnknownColor::Fail => return Err(ImageError::UnknownColor { x, y, color }),
+                },
+            };
+            if let Some(element) = element {
+                map.spawn_cell(&WorldPosition::new(x, y), element);
+            }
+        }
+    }
+    Ok(map)
+}
+
+/// Color of the element in 8-bit channels.
+pub fn element_color(registry: &ElementRegistry, element: Element) -> [u8; 3] {
+    to_rgb8(registry.get(element).color)
+}
+
+pub(crate) fn to_rgb8((r, g, b): (f32, f32, f32)) -> [u8; 3] {
+    [r, g, b].map(|channel| (channel.clamp(0., 1.) * 255.).round() as u8)
+}
+
+fn closest_element(palette: &[(Element, [u8; 3])], color: [u8; 3]) -> Option<(Element, f32)> {
+    palette
+        .iter()
+        .map(|(element, element_color)| {
+            let distance = element_color
+                .iter()
+                .zip(color)
+                .map(|(a, b)| (*a as f32 - b as f32).powi(2))
+                .sum::<f32>()
+                .sqrt();
+            (*element, distance)
+        })
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+    use crate::test_utils::voxel;
+    use crate::texture::MapTexture;
+    use crate::voxels::Voxel;
+
+    fn png(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(pixels)
+            .unwrap();
+        bytes
+    }
+
+    fn element(map: &GameMap, x: usize, y: usize) -> Option<String> {
+        match map.get_cell(&WorldPosition::new(x, y)) {
+            Some(Voxel::of { data }) => Some(map.registry().get(data.element).name.clone()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn exported_maps_are_imported_back() {
+        let mut map = GameMap::new(6, 4);
+        map.set_cell(&WorldPosition::new(0, 0), &voxel("sand"));
+        map.set_cell(&WorldPosition::new(5, 3), &voxel("water"));
+        map.set_cell(&WorldPosition::new(2, 1), &voxel("stone"));
+        let mut bytes = Vec::new();
+        export_png(&mut bytes, &map).unwrap();
+
+        let imported = import_png(
+            bytes.as_slice(),
+            ElementRegistry::default(),
+            &ImportOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!((imported.width, imported.height), (6, 4));
+        assert_eq!(imported.iter().count(), 3);
+        assert_eq!(element(&imported, 0, 0).as_deref(), Some("sand"));
+        assert_eq!(element(&imported, 5, 3).as_deref(), Some("water"));
+        assert_eq!(element(&imported, 2, 1).as_deref(), Some("stone"));
+    }
+
+    #[test]
+    fn exports_are_colored_like_on_screen() {
+        let mut map = GameMap::new(3, 1);
+        let sand = map.registry().find("sand").unwrap();
+        let shaded = map.registry().spawn_shaded_voxel(sand, &mut Rng::new(1));
+        map.set_cell(&WorldPosition::new(0, 0), &shaded);
+        map.set_cell(&WorldPosition::new(1, 0), &shaded);
+        map.set_cell(&WorldPosition::new(2, 0), &voxel("water"));
+        let mut bytes = Vec::new();
+        export_png(&mut bytes, &map).unwrap();
+
+        let mut decoder = png::Decoder::new(bytes.as_slice()).read_info().unwrap();
+        let mut pixels = vec![0; decoder.output_buffer_size()];
+        decoder.next_frame(&mut pixels).unwrap();
+        let texture = MapTexture::from_map(&map);
+        assert_eq!(pixels, texture.pixels());
+        // The wet sand next to the water is darker
+        assert_ne!(pixels[..4], pixels[4..8]);
+
+        let imported = import_png(
+            bytes.as_slice(),
+            ElementRegistry::default(),
+            &ImportOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(element(&imported, 0, 0).as_deref(), Some("sand"));
+        assert_eq!(element(&imported, 1, 0).as_deref(), Some("sand"));
+    }
+
+    #[test]
+    fn colors_within_the_tolerance_match_the_element() {
+        let registry = ElementRegistry::default();
+        let [r, g, b] = element_color(&registry, registry.find("sand").unwrap());
+        let bytes = png(2, 1, &[r - 5, g + 3, b + 4, 255, 0, 255]);
+
+        let import = |unknown_color| {
+            let options = ImportOptions {
+                unknown_color,
+                ..ImportOptions::default()
+            };
+            import_png(bytes.as_slice(), ElementRegistry::default(), &options)
+        };
+
+        let map = import(UnknownColor::Empty).unwrap();
+        assert_eq!(element(&map, 0, 0).as_deref(), Some("sand"));
+        assert_eq!(element(&map, 1, 0), None);
+        assert!(element(&import(UnknownColor::Nearest).unwrap(), 1, 0).is_some());
+        assert!(matches!(
+            import(UnknownColor::Fail),
+            Err(ImageError::UnknownColor { x: 1, y: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn large_images_are_refused_before_decoding() {
+        let bytes = png(3, 2, &[0; 18]);
+        let options = ImportOptions {
+            max_size: Some((2, 2)),
+            ..ImportOptions::default()
+        };
+
+        assert!(matches!(
+            import_png(bytes.as_slice(), ElementRegistry::default(), &options),
+            Err(ImageError::TooLarge {
+                width: 3,
+                height: 2
+            })
+        ));
+        // One bit per pixel keeps the file small, it still has more pixels than any map
+        let (width, height) = (1 << 12, 1 << 13);
+        let mut huge = Vec::new();
+        let mut encoder = png::Encoder::new(&mut huge, width, height);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::One);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(&vec![0; (width / 8 * height) as usize])
+            .unwrap();
+        assert!(matches!(
+            import_png(
+                huge.as_slice(),
+                ElementRegistry::default(),
+                &ImportOptions::default()
+            ),
+            Err(ImageError::TooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn thumbnails_keep_one_cell_out_of_scale() {
+        let mut map = GameMap::new(8, 8);
+        map.set_cell(&WorldPosition::new(0, 7), &voxel("sand"));
+        let mut bytes = Vec::new();
+        export_thumbnail(&mut bytes, &map, 4).unwrap();
+
+        let thumbnail = import_png(
+            bytes.as_slice(),
+            ElementRegistry::default(),
+            &ImportOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!((thumbnail.width, thumbnail.height), (2, 2));
+        assert_eq!(element(&thumbnail, 0, 1).as_deref(), Some("sand"));
+    }
+}
@@ -0,0 +1,58 @@
+//! Headless falling-sand simulation.
+//!
+//! This crate owns the voxel grid and the rules moving voxels around. It has no
+//! dependency on a window, a GPU or an ECS: tools, tests and servers can build a
+//! [`GameMap`], paint cells into it and call [`GameMap::step`] to advance the
+//! simulation, optionally paced by a [`SimulationClock`]. A [`SimulationBackend`]
+//! runs the same rules elsewhere, such as on the GPU with the `gpu` feature. The
+//! Bevy game is a front-end over it.
+
+pub mod backend;
+pub mod brush;
+pub mod chunk;
+pub mod clock;
+pub mod elements;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+pub mod image;
+pub mod map;
+pub mod rng;
+pub mod save;
+pub mod streaming;
+pub mod terrain;
+pub mod texture;
+pub mod voxels;
+pub mod world_position;
+
+pub use backend::{CpuBackend, Simulation, SimulationBackend, SimulationError};
+pub use brush::{outline, Brush, BrushShape, MAX_RADIUS};
+pub use chunk::{Chunk, DirtyRect, CHUNK_SIZE};
+pub use clock::SimulationClock;
+pub use elements::{Element, ElementDefinition, ElementRegistry, Kind, Reaction, RegistryError};
+#[cfg(feature = "gpu")]
+pub use gpu::{GpuBackend, GpuError};
+pub use image::{
+    element_color, export_png, export_thumbnail, import_png, ImageError, ImportOptions,
+    UnknownColor,
+};
+pub use map::GameMap;
+pub use save::{load_world, save_world, SaveError, WorldInfo, FORMAT_VERSION};
+pub use streaming::{
+    ChunkStore, ChunkStoreError, ColumnGenerator, EmptyColumns, StoredColumn, StreamedWorld,
+};
+pub use terrain::{TerrainConfig, TerrainGenerator};
+pub use texture::{cell_color, voxel_color, MapTexture, TextureRegion, FIRE_COLOR};
+pub use voxels::{Move, Voxel, VoxelStruct};
+pub use world_position::WorldPosition;
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use crate::elements::ElementRegistry;
+    use crate::voxels::Voxel;
+
+    /// Voxel of the default element called `name`.
+    pub fn voxel(name: &str) -> Voxel {
+        let registry = ElementRegistry::default();
+        registry.spawn_voxel(registry.find(name).unwrap())
+    }
+}
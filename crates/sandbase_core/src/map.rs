@@ -0,0 +1,1591 @@
+use std::sync::Arc;
+
+use bevy_tasks::{ComputeTaskPool, TaskPool};
+
+use crate::chunk::{Chunk, DirtyRect, CHUNK_SIZE};
+use crate::elements::{Element, ElementRegistry, AMBIENT_TEMPERATURE, BURN_TEMPERATURE};
+use crate::rng::Rng;
+use crate::voxels::{Move, Voxel, VoxelStruct};
+use crate::world_position::WorldPosition;
+
+/// Conductivity of the empty cells, voxels slowly cool down or warm up to the
+/// ambient temperature through them.
+pub(crate) const AIR_CONDUCTIVITY: f32 = 0.02;
+
+/// Velocity gained per tick by falling voxels, in cells per tick.
+pub(crate) const GRAVITY: f32 = 0.25;
+
+/// Voxels hitting an obstacle at least this fast splash sideways.
+pub(crate) const SPLASH_VELOCITY: f32 = 2.;
+
+/// Cells around a chunk a tile update can reach. Tiles updated at the same time
+/// are a chunk apart, so their areas never overlap.
+pub(crate) const TILE_MARGIN: usize = CHUNK_SIZE / 2;
+
+/// Most cells a map read from a file may have, far above any playable size, so
+/// that corrupt or oversized files are rejected before allocating the map.
+pub(crate) const MAX_CELLS: usize = 1 << 24;
+
+/// Temperature change below which a voxel counts as thermally settled, in °C.
+const HEAT_EPSILON: f32 = 0.01;
+
+/// Chance per tick of a burning voxel releasing smoke when the cell above is free.
+pub(crate) const SMOKE_CHANCE: f32 = 0.1;
+
+/// Chance per tick of a burning voxel releasing flames when the cell above is free
+/// and it releases no smoke.
+pub(crate) const FLAME_CHANCE: f32 = 0.2;
+
+/// The voxel grid, single source of truth of the world. Cells are stored
+/// contiguously row by row, starting from the bottom-left corner.
+///
+/// Every cell written since the last call to [`GameMap::take_changes`] is recorded
+/// so front-ends only have to redraw what actually changed.
+///
+/// The map is split into [`Chunk`]s of [`CHUNK_SIZE`] cells. Writing a cell wakes
+/// it and its neighbours up, and a tick only updates the cells woken up during the
+/// previous one, so settled areas cost nothing to simulate. Chunks are updated in
+/// four passes of a checkerboard pattern, the chunks of a pass in parallel.
+///
+/// Ticks are deterministic: the same map stepped the same number of times always
+/// ends up in the same state.
+#[cfg_attr(feature = "bevy", derive(bevy_ecs::system::Resource))]
+#[derive(Clone, Debug)]
+pub struct GameMap {
+    pub width: usize,
+    pub height: usize,
+    /// Position of the bottom-left cell: the origin for whole maps, the corner of
+    /// the area for the copies used to update tiles in parallel.
+    origin: WorldPosition,
+    cells: Vec<Option<VoxelStruct>>,
+    changed: Vec<bool>,
+    changes: Vec<usize>,
+    /// Generation during which each cell was last written, cells written during
+    /// the current one are not updated again.
+    updated: Vec<u64>,
+    generation: u64,
+    chunks: Vec<Chunk>,
+    /// Number of chunks in a row of chunks.
+    chunks_width: usize,
+    /// All the cells of the whole map, which the cells woken up by tile copies can
+    /// lie anywhere in.
+    bounds: DirtyRect,
+    /// Areas woken up in a tile copy, woken up in the whole map once merged back.
+    /// `None` for whole maps, which wake their chunks directly.
+    woken: Option<Vec<DirtyRect>>,
+    tick: u64,
+    /// Seed of the random behaviours, such as gases spreading.
+    pub seed: u64,
+    /// Temperature of the empty cells, in °C.
+    pub ambient_temperature: f32,
+    /// Temperatures of the next tick by cell index, kept to avoid allocating every
+    /// tick.
+    heat_buffer: Vec<(usize, f32)>,
+    registry: Arc<ElementRegistry>,
+}
+
+impl GameMap {
+    /// Creates an empty map using the default elements.
+    pub fn new(width: usize, height: usize) -> Self {
+        GameMap::with_registry(width, height, ElementRegistry::default())
+    }
+
+    pub fn with_registry(width: usize, height: usize, registry: ElementRegistry) -> Self {
+        GameMap::empty(WorldPosition::default(), width, height, Arc::new(registry))
+    }
+
+    fn empty(
+        origin: WorldPosition,
+        width: usize,
+        height: usize,
+        registry: Arc<ElementRegistry>,
+    ) -> Self {
+        GameMap {
+            width,
+            height,
+            origin,
+            cells: vec![None; width * height],
+            changed: vec![false; width * height],
+            changes: Vec::new(),
+            updated: vec![0; width * height],
+            generation: 0,
+            chunks: vec![
+                Chunk::default();
+                width.div_ceil(CHUNK_SIZE) * height.div_ceil(CHUNK_SIZE)
+            ],
+            chunks_width: width.div_ceil(CHUNK_SIZE),
+            bounds: DirtyRect::new(
+                origin,
+                WorldPosition::new(origin.x + width - 1, origin.y + height - 1),
+            ),
+            woken: None,
+            tick: 0,
+            seed: 0,
+            ambient_temperature: AMBIENT_TEMPERATURE,
+            heat_buffer: Vec::new(),
+            registry,
+        }
+    }
+
+    pub fn registry(&self) -> &ElementRegistry {
+        &self.registry
+    }
+
+    pub fn contains(&self, world_position: &WorldPosition) -> bool {
+        (self.origin.x..self.origin.x + self.width).contains(&world_position.x)
+            && (self.origin.y..self.origin.y + self.height).contains(&world_position.y)
+    }
+
+    /// Returns the voxel at `world_position`, `Some(Voxel::OOB)` outside of the map.
+    pub fn get_cell(&self, world_position: &WorldPosition) -> Option<Voxel> {
+        if self.contains(world_position) {
+            self.cells[self.index(world_position)].map(|data| Voxel::of { data })
+        } else {
+            Some(Voxel::OOB)
+        }
+    }
+
+    /// Writes `voxel` at `world_position`. Out of bounds positions and
+    /// `Voxel::OOB` are ignored.
+    pub fn set_cell(&mut self, world_position: &WorldPosition, voxel: &Voxel) {
+        if let (true, Voxel::of { data }) = (self.contains(world_position), voxel) {
+            let index = self.index(world_position);
+            self.cells[index] = Some(*data);
+            self.mark_changed(index);
+        }
+    }
+
+    /// Writes a new voxel of `element` at `world_position`, with a random shade.
+    pub fn spawn_cell(&mut self, world_position: &WorldPosition, element: Element) {
+        let voxel = self
+            .registry
+            .spawn_shaded_voxel(element, &mut self.rng(*world_position));
+        self.set_cell(world_position, &voxel);
+    }
+
+    pub fn delete_cell(&mut self, world_position: &WorldPosition) {
+        if self.contains(world_position) {
+            let index = self.index(world_position);
+            self.cells[index] = None;
+            self.mark_changed(index);
+        }
+    }
+
+    /// Iterates over the occupied cells.
+    pub fn iter(&self) -> impl Iterator<Item = (WorldPosition, &VoxelStruct)> + '_ {
+        self.cells
+            .iter()
+            .enumerate()
+            .filter_map(move |(index, cell)| cell.as_ref().map(|data| (self.position(index), data)))
+    }
+
+    /// Whether the chunk containing `world_position` will be updated next tick.
+    pub fn is_awake(&self, world_position: &WorldPosition) -> bool {
+        self.contains(world_position)
+            && self.chunks[self.chunk_index(
+                (world_position.x - self.origin.x) / CHUNK_SIZE,
+                (world_position.y - self.origin.y) / CHUNK_SIZE,
+            )]
+            .is_awake()
+    }
+
+    /// Number of chunks that will be updated next tick.
+    pub fn awake_chunks(&self) -> usize {
+        self.chunks.iter().filter(|chunk| chunk.is_awake()).count()
+    }
+
+    /// All the cells, row by row from the bottom-left corner.
+    pub(crate) fn cells(&self) -> &[Option<VoxelStruct>] {
+        &self.cells
+    }
+
+    /// Cells each chunk will update next tick.
+    pub(crate) fn woken_chunks(&self) -> Vec<Option<DirtyRect>> {
+        self.chunks.iter().map(Chunk::woken).collect()
+    }
+
+    /// Puts the map back in a saved state: `cells` and `woken` must be laid out like
+    /// [`GameMap::cells`] and [`GameMap::woken_chunks`]. All the cells count as
+    /// changed.
+    pub(crate) fn restore(
+        &mut self,
+        tick: u64,
+        cells: Vec<Option<VoxelStruct>>,
+        woken: &[Option<DirtyRect>],
+    ) {
+        self.tick = tick;
+        self.cells = cells;
+        for (chunk, rect) in self.chunks.iter_mut().zip(woken) {
+            *chunk = Chunk::default();
+            if let Some(rect) = rect {
+                chunk.wake(*rect);
+            }
+        }
+        self.changes = (0..self.cells.len()).collect();
+        self.changed.fill(true);
+    }
+
+    /// Writes the cells computed `ticks` ticks later by another backend, laid out
+    /// like [`GameMap::cells`]. Only the cells that differ count as changed.
+    #[cfg(feature = "gpu")]
+    pub(crate) fn advance_to(&mut self, ticks: u64, cells: Vec<Option<VoxelStruct>>) {
+        self.generation += 1;
+        for (index, cell) in cells.into_iter().enumerate() {
+            if self.cells[index] != cell {
+                self.cells[index] = cell;
+                self.mark_changed(index);
+            }
+        }
+        self.tick += ticks;
+    }
+
+    /// Number of chunk columns of the map.
+    pub fn chunks_width(&self) -> usize {
+        self.chunks_width
+    }
+
+    /// Cells of the chunk column `chunk_x`, row by row from the bottom.
+    pub fn column_cells(&self, chunk_x: usize) -> Vec<Option<VoxelStruct>> {
+        let area = self.chunk_area(chunk_x, 0);
+        (0..self.height)
+            .flat_map(|y| {
+                let start = self.index(&WorldPosition::new(area.min.x, y));
+                self.cells[start..=start + area.max.x - area.min.x]
+                    .iter()
+                    .copied()
+            })
+            .collect()
+    }
+
+    /// Replaces the cells of the chunk column `chunk_x` with `cells`, laid out like
+    /// [`GameMap::column_cells`].
+    pub fn set_column_cells(&mut self, chunk_x: usize, cells: &[Option<VoxelStruct>]) {
+        let area = self.chunk_area(chunk_x, 0);
+        let column_width = area.max.x - area.min.x + 1;
+        for (i, cell) in cells.iter().enumerate().take(column_width * self.height) {
+            let world_position =
+                WorldPosition::new(area.min.x + i % column_width, i / column_width);
+            match cell {
+                Some(data) => self.set_cell(&world_position, &Voxel::of { data: *data }),
+                None => self.delete_cell(&world_position),
+            }
+        }
+    }
+
+    /// Moves every cell `chunks` chunk columns to the left, or to the right when
+    /// negative, emptying the columns left behind. All the cells count as changed.
+    pub fn scroll_chunks(&mut self, chunks: isize) {
+        let shift = chunks.unsigned_abs() * CHUNK_SIZE;
+        for y in 0..self.height {
+            let row = &mut self.cells[y * self.width..(y + 1) * self.width];
+            let kept = self.width.saturating_sub(shift);
+            if chunks > 0 {
+                row.copy_within(self.width - kept.., 0);
+                row[kept..].fill(None);
+            } else {
+                row.copy_within(..kept, self.width - kept);
+                row[..self.width - kept].fill(None);
+            }
+        }
+        // Dirty rectangles would have to be moved as well, update everything once
+        self.chunks.fill(Chunk::default());
+        self.wake_area(self.area());
+        for index in 0..self.cells.len() {
+            if !self.changed[index] {
+                self.changed[index] = true;
+                self.changes.push(index);
+            }
+        }
+    }
+
+    /// Returns the positions written since the previous call, each at most once.
+    pub fn take_changes(&mut self) -> Vec<WorldPosition> {
+        let changes = std::mem::take(&mut self.changes);
+        changes
+            .into_iter()
+            .map(|index| {
+                self.changed[index] = false;
+                self.position(index)
+            })
+            .collect()
+    }
+
+    pub fn get_left_voxel(&self, world_position: WorldPosition) -> (Option<Voxel>, WorldPosition) {
+        self.get_relative_voxel(world_position, -1, 0)
+    }
+
+    pub fn get_right_voxel(&self, world_position: WorldPosition) -> (Option<Voxel>, WorldPosition) {
+        self.get_relative_voxel(world_position, 1, 0)
+    }
+
+    /// Up to `range` voxels on the left, closest first, stopping at the map border.
+    pub fn get_left_voxels(
+        &self,
+        world_position: WorldPosition,
+        range: usize,
+    ) -> Vec<(Option<Voxel>, WorldPosition)> {
+        self.get_horizontal_voxels(world_position, -1, range)
+    }
+
+    /// Up to `range` voxels on the right, closest first, stopping at the map border.
+    pub fn get_right_voxels(
+        &self,
+        world_position: WorldPosition,
+        range: usize,
+    ) -> Vec<(Option<Voxel>, WorldPosition)> {
+        self.get_horizontal_voxels(world_position, 1, range)
+    }
+
+    pub fn get_top_voxel(&self, world_position: WorldPosition) -> (Option<Voxel>, WorldPosition) {
+        self.get_relative_voxel(world_position, 0, 1)
+    }
+
+    pub fn get_top_left_voxel(
+        &self,
+        world_position: WorldPosition,
+    ) -> (Option<Voxel>, WorldPosition) {
+        self.get_relative_voxel(world_position, -1, 1)
+    }
+
+    pub fn get_top_right_voxel(
+        &self,
+        world_position: WorldPosition,
+    ) -> (Option<Voxel>, WorldPosition) {
+        self.get_relative_voxel(world_position, 1, 1)
+    }
+
+    pub fn get_bottom_voxel(
+        &self,
+        world_position: WorldPosition,
+    ) -> (Option<Voxel>, WorldPosition) {
+        self.get_relative_voxel(world_position, 0, -1)
+    }
+
+    pub fn get_bottom_left_voxel(
+        &self,
+        world_position: WorldPosition,
+    ) -> (Option<Voxel>, WorldPosition) {
+        self.get_relative_voxel(world_position, -1, -1)
+    }
+
+    pub fn get_bottom_right_voxel(
+        &self,
+        world_position: WorldPosition,
+    ) -> (Option<Voxel>, WorldPosition) {
+        self.get_relative_voxel(world_position, 1, -1)
+    }
+
+    /// Voxel one column to the left and two rows below.
+    pub fn get_bottom2_left_voxel(
+        &self,
+        world_position: WorldPosition,
+    ) -> (Option<Voxel>, WorldPosition) {
+        self.get_relative_voxel(world_position, -1, -2)
+    }
+
+    /// Voxel one column to the right and two rows below.
+    pub fn get_bottom2_right_voxel(
+        &self,
+        world_position: WorldPosition,
+    ) -> (Option<Voxel>, WorldPosition) {
+        self.get_relative_voxel(world_position, 1, -2)
+    }
+
+    /// Applies a move computed by [`Voxel::update`] for the voxel at `world_position`.
+    /// Returns whether the map changed.
+    pub fn apply_move(&mut self, world_position: WorldPosition, voxel_move: Move) -> bool {
+        match (voxel_move, self.get_cell(&world_position)) {
+            (Move::Displace(new_world_position), Some(voxel @ Voxel::of { .. })) => {
+                self.delete_cell(&world_position);
+                self.set_cell(&new_world_position, &voxel);
+                true
+            }
+            (Move::Swap(new_world_position), Some(voxel @ Voxel::of { .. })) => {
+                match self.get_cell(&new_world_position) {
+                    Some(other_voxel @ Voxel::of { .. }) => {
+                        self.set_cell(&world_position, &other_voxel);
+                        self.set_cell(&new_world_position, &voxel);
+                    }
+                    None => {
+                        self.delete_cell(&world_position);
+                        self.set_cell(&new_world_position, &voxel);
+                    }
+                    // Can't swap with the outside of the world
+                    Some(Voxel::OOB) => return false,
+                }
+                true
+            }
+            (Move::React { other, reaction }, Some(Voxel::of { .. })) => {
+                self.replace_cell(&world_position, reaction.into);
+                self.replace_cell(&other, reaction.other_into);
+                true
+            }
+            (Move::Ignite(other), Some(Voxel::of { .. })) => match self.get_cell(&other) {
+                Some(Voxel::of { mut data }) => {
+                    data.burning = true;
+                    data.lifetime = Some(self.registry.get(data.element).burn_time);
+                    self.set_cell(&other, &Voxel::of { data });
+                    true
+                }
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Puts a new voxel of `element` at `world_position`, or empties the cell.
+    fn replace_cell(&mut self, world_position: &WorldPosition, element: Option<Element>) {
+        match element {
+            Some(element) => {
+                let voxel = self
+                    .registry
+                    .spawn_shaded_voxel(element, &mut self.rng(*world_position));
+                self.set_cell(world_position, &voxel);
+            }
+            None => self.delete_cell(world_position),
+        }
+    }
+
+    /// Number of ticks simulated so far.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Advances the simulation by one tick, updating every voxel at most once.
+    /// Returns the number of voxels that moved.
+    ///
+    /// The dirty cells of each chunk are scanned bottom-up, so falling voxels land
+    /// in rows already updated. The horizontal direction alternates from one row to
+    /// the next and from one tick to the next to avoid drifting to one side. Heat is
+    /// exchanged once every voxel has moved.
+    pub fn step(&mut self) -> usize {
+        self.generation += 1;
+        self.chunks.iter_mut().for_each(Chunk::start_tick);
+        let moved = (0..4).map(|pass| self.update_tiles(pass)).sum();
+        self.update_temperatures();
+        self.tick += 1;
+        moved
+    }
+
+    /// Updates the dirty cells of the chunks of a checkerboard `pass`, each on its
+    /// own copy of the area it can reach so they can run in parallel. Returns the
+    /// number of voxels that moved.
+    fn update_tiles(&mut self, pass: usize) -> usize {
+        let tiles: Vec<(GameMap, DirtyRect)> = (0..self.chunks.len())
+            .filter_map(|chunk_index| {
+                let chunk_x = chunk_index % self.chunks_width;
+                let chunk_y = chunk_index / self.chunks_width;
+                if chunk_x % 2 + 2 * (chunk_y % 2) != pass {
+                    return None;
+                }
+                let dirty = self.chunks[chunk_index].dirty?;
+                let area = DirtyRect::around(
+                    &self.chunk_area(chunk_x, chunk_y),
+                    TILE_MARGIN,
+                    &self.area(),
+                );
+                Some((self.window(area), dirty))
+            })
+            .collect();
+
+        let results = match tiles.len() {
+            0 => return 0,
+            1 => tiles
+                .into_iter()
+                .map(|(mut window, dirty)| {
+                    let moved = window.update_rect(dirty);
+                    (window, moved)
+                })
+                .collect(),
+            _ => ComputeTaskPool::init(TaskPool::default).scope(|scope| {
+                for (mut window, dirty) in tiles {
+                    scope.spawn(async move {
+                        let moved = window.update_rect(dirty);
+                        (window, moved)
+                    });
+                }
+            }),
+        };
+        results
+            .into_iter()
+            .map(|(window, moved)| {
+                self.merge(window);
+                moved
+            })
+            .sum()
+    }
+
+    /// Updates the cells of `rect` row by row.
+    fn update_rect(&mut self, rect: DirtyRect) -> usize {
+        let mut moved = 0;
+        for y in rect.min.y..=rect.max.y {
+            let left_to_right = (y as u64 + self.tick).is_multiple_of(2);
+            for i in 0..=(rect.max.x - rect.min.x) {
+                let x = if left_to_right {
+                    rect.min.x + i
+                } else {
+                    rect.max.x - i
+                };
+                if self.update_cell(WorldPosition { x, y }) {
+                    moved += 1;
+                }
+            }
+        }
+        moved
+    }
+
+    /// Copy of the cells of `area`, sharing the state of the current tick.
+    fn window(&self, area: DirtyRect) -> GameMap {
+        let mut window = GameMap::empty(
+            area.min,
+            area.max.x - area.min.x + 1,
+            area.max.y - area.min.y + 1,
+            Arc::clone(&self.registry),
+        );
+        for y in 0..window.height {
+            let start = self.index(&WorldPosition::new(area.min.x, area.min.y + y));
+            let window_start = y * window.width;
+            window.cells[window_start..window_start + window.width]
+                .copy_from_slice(&self.cells[start..start + window.width]);
+            window.updated[window_start..window_start + window.width]
+                .copy_from_slice(&self.updated[start..start + window.width]);
+        }
+        window.bounds = self.area();
+        window.woken = Some(Vec::new());
+        window.generation = self.generation;
+        window.tick = self.tick;
+        window.seed = self.seed;
+        window.ambient_temperature = self.ambient_temperature;
+        window
+    }
+
+    /// Writes back a window updated in parallel, with its changes and woken cells.
+    fn merge(&mut self, window: GameMap) {
+        for y in 0..window.height {
+            let start = self.index(&WorldPosition::new(window.origin.x, window.origin.y + y));
+            let window_start = y * window.width;
+            self.cells[start..start + window.width]
+                .copy_from_slice(&window.cells[window_start..window_start + window.width]);
+            self.updated[start..start + window.width]
+                .copy_from_slice(&window.updated[window_start..window_start + window.width]);
+        }
+        for window_index in window.changes.iter() {
+            let index = self.index(&window.position(*window_index));
+            if !self.changed[index] {
+                self.changed[index] = true;
+                self.changes.push(index);
+            }
+        }
+        for area in window.woken.into_iter().flatten() {
+            self.wake_area(area);
+        }
+    }
+
+    /// Exchanges heat between neighbouring voxels of the dirty cells, and with the
+    /// air next to empty cells, then applies the phase changes. New temperatures only
+    /// depend on the previous ones so the result doesn't depend on the scan order.
+    /// Voxels whose temperature still changes stay awake.
+    fn update_temperatures(&mut self) {
+        let registry = Arc::clone(&self.registry);
+        let mut temperatures = std::mem::take(&mut self.heat_buffer);
+        temperatures.clear();
+        // Voxels kept from exchanging heat with a neighbour that isn't updated
+        let mut unsettled = Vec::new();
+        for rect in self.chunks.iter().filter_map(|chunk| chunk.dirty) {
+            for y in rect.min.y..=rect.max.y {
+                for x in rect.min.x..=rect.max.x {
+                    let world_position = WorldPosition { x, y };
+                    let index = self.index(&world_position);
+                    let data = match self.cells[index] {
+                        Some(data) => data,
+                        None => continue,
+                    };
+                    let conductivity = registry.get(data.element).conductivity;
+                    let mut temperature = data.temperature;
+                    for (dx, dy) in [(0, -1), (-1, 0), (1, 0), (0, 1)] {
+                        if conductivity <= 0. {
+                            break;
+                        }
+                        let (other_temperature, other_conductivity) =
+                            match self.get_relative_voxel(world_position, dx, dy) {
+                                // Only exchanged when both sides are updated, so
+                                // no heat is lost, once the neighbour wakes up
+                                (Some(Voxel::of { data: other }), other_position)
+                                    if !self.is_dirty(&other_position) =>
+                                {
+                                    if (other.temperature - data.temperature).abs() > HEAT_EPSILON {
+                                        unsettled.push(world_position);
+                                    }
+                                    continue;
+                                }
+                                (Some(Voxel::of { data: other }), _) => {
+                                    (other.temperature, registry.get(other.element).conductivity)
+                                }
+                                (None, _) => (self.ambient_temperature, AIR_CONDUCTIVITY),
+                                (Some(Voxel::OOB), _) => continue,
+                            };
+                        temperature += (other_temperature - data.temperature)
+                            * conductivity.min(other_conductivity)
+                            / 4.;
+                    }
+                    temperatures.push((index, temperature));
+                }
+            }
+        }
+
+        for (index, temperature) in temperatures.iter() {
+            let mut data = match self.cells[*index] {
+                Some(data) => data,
+                None => continue,
+            };
+            let world_position = self.position(*index);
+            if (data.temperature - temperature).abs() > HEAT_EPSILON {
+                self.wake(world_position);
+            }
+            data.temperature = *temperature;
+            self.cells[*index] = Some(data);
+
+            let phase_changes = registry.phase_changes(data.element);
+            let new_element = match (phase_changes.cools_into, phase_changes.heats_into) {
+                (Some((threshold, element)), _) if data.temperature < threshold => element,
+                (_, Some((threshold, element))) if data.temperature > threshold => element,
+                _ => continue,
+            };
+            let mut voxel = registry.spawn_voxel(new_element);
+            if let Voxel::of { data: new_data } = &mut voxel {
+                new_data.temperature = data.temperature;
+                new_data.shade = data.shade;
+            }
+            self.set_cell(&world_position, &voxel);
+        }
+        for world_position in unsettled {
+            self.wake(world_position);
+        }
+        self.heat_buffer = temperatures;
+    }
+
+    /// Random number generator for the voxel at `world_position` during the current
+    /// tick. It only depends on the map seed, the tick and the position.
+    pub fn rng(&self, world_position: WorldPosition) -> Rng {
+        Rng::for_cell(self.seed, self.tick, world_position.x, world_position.y)
+    }
+
+    /// Ages the voxel at `world_position`, then moves it up to `velocity` cells, one
+    /// cell at a time so it can't go through other voxels. A voxel falling the whole
+    /// way speeds up, one stopped by an obstacle loses its momentum and may splash.
+    fn update_cell(&mut self, world_position: WorldPosition) -> bool {
+        let index = self.index(&world_position);
+        let mut data = match self.cells[index] {
+            Some(data) if self.updated[index] != self.generation => data,
+            _ => return false,
+        };
+        if data.burning {
+            self.burn(world_position, &mut data);
+        }
+        match data.lifetime {
+            Some(0) if data.burning => {
+                let burns_into = self.registry.burns_into(data.element);
+                self.replace_cell(&world_position, burns_into);
+                return true;
+            }
+            Some(0) => {
+                self.delete_cell(&world_position);
+                return true;
+            }
+            Some(lifetime) => {
+                data.lifetime = Some(lifetime - 1);
+                self.cells[index] = Some(data);
+                self.wake(world_position);
+            }
+            None => (),
+        }
+        let voxel = Voxel::of { data };
+        let mut current_position = world_position;
+        let mut moved = false;
+        let steps = (data.velocity as usize).max(1);
+        let mut falls = 0;
+        for _ in 0..steps {
+            match voxel.update(self, current_position) {
+                Some(voxel_move) if self.apply_move(current_position, voxel_move) => {
+                    moved = true;
+                    match voxel_move.destination() {
+                        Some(destination) => {
+                            if destination.y < current_position.y {
+                                falls += 1;
+                            }
+                            current_position = destination;
+                        }
+                        // The voxel reacted and may not exist anymore
+                        None => return moved,
+                    }
+                }
+                _ => break,
+            }
+        }
+        if !moved && voxel.is_reactive(self, world_position) {
+            self.wake(world_position);
+        }
+
+        let velocity = if falls == steps {
+            (data.velocity + GRAVITY).min(self.registry.get(data.element).speed)
+        } else {
+            if data.velocity >= SPLASH_VELOCITY {
+                if let Some(splash) = voxel.splash(self, current_position, data.velocity) {
+                    if self.apply_move(current_position, splash) {
+                        moved = true;
+                        current_position = splash.destination().unwrap_or(current_position);
+                    }
+                }
+            }
+            0.
+        };
+        let current_index = self.index(&current_position);
+        if let Some(current) = &mut self.cells[current_index] {
+            current.velocity = velocity;
+        }
+        moved
+    }
+
+    /// Keeps a burning voxel hot and now and then releases smoke or flames in the
+    /// cell above.
+    fn burn(&mut self, world_position: WorldPosition, data: &mut VoxelStruct) {
+        data.temperature = data.temperature.max(BURN_TEMPERATURE);
+        let (maybe_voxel, top_position) = self.get_top_voxel(world_position);
+        if maybe_voxel.is_some() {
+            return;
+        }
+        let mut rng = self.rng(world_position);
+        let released = if rng.chance(SMOKE_CHANCE) {
+            self.registry.burns_into(data.element)
+        } else if rng.chance(FLAME_CHANCE) {
+            self.registry.flames(data.element)
+        } else {
+            None
+        };
+        if let Some(element) = released {
+            let voxel = self
+                .registry
+                .spawn_shaded_voxel(element, &mut self.rng(top_position));
+            self.set_cell(&top_position, &voxel);
+        }
+    }
+
+    /// Index of `world_position` in the cell array, the position must be in the map.
+    pub fn index(&self, world_position: &WorldPosition) -> usize {
+        (world_position.y - self.origin.y) * self.width + world_position.x - self.origin.x
+    }
+
+    pub fn position(&self, index: usize) -> WorldPosition {
+        WorldPosition {
+            x: self.origin.x + index % self.width,
+            y: self.origin.y + index / self.width,
+        }
+    }
+
+    /// All the cells of the map.
+    fn area(&self) -> DirtyRect {
+        DirtyRect::new(
+            self.origin,
+            WorldPosition::new(
+                self.origin.x + self.width - 1,
+                self.origin.y + self.height - 1,
+            ),
+        )
+    }
+
+    fn mark_changed(&mut self, index: usize) {
+        self.updated[index] = self.generation;
+        if !self.changed[index] {
+            self.changed[index] = true;
+            self.changes.push(index);
+        }
+        self.wake(self.position(index));
+    }
+
+    fn chunk_index(&self, chunk_x: usize, chunk_y: usize) -> usize {
+        chunk_y * self.chunks_width + chunk_x
+    }
+
+    /// Whether `world_position` is updated during the current tick.
+    fn is_dirty(&self, world_position: &WorldPosition) -> bool {
+        let chunk_index = self.chunk_index(
+            (world_position.x - self.origin.x) / CHUNK_SIZE,
+            (world_position.y - self.origin.y) / CHUNK_SIZE,
+        );
+        self.chunks[chunk_index]
+            .dirty
+            .is_some_and(|rect| rect.contains(world_position))
+    }
+
+    /// Schedules `world_position` and its neighbours for an update next tick.
+    fn wake(&mut self, world_position: WorldPosition) {
+        let cell = DirtyRect::new(world_position, world_position);
+        self.wake_area(DirtyRect::around(&cell, 1, &self.bounds));
+    }
+
+    /// Schedules the cells of `area` for an update next tick.
+    fn wake_area(&mut self, area: DirtyRect) {
+        if let Some(woken) = &mut self.woken {
+            woken.push(area);
+            return;
+        }
+        let min_chunk_x = (area.min.x - self.origin.x) / CHUNK_SIZE;
+        let max_chunk_x = (area.max.x - self.origin.x) / CHUNK_SIZE;
+        for chunk_y in
+            (area.min.y - self.origin.y) / CHUNK_SIZE..=(area.max.y - self.origin.y) / CHUNK_SIZE
+        {
+            for chunk_x in min_chunk_x..=max_chunk_x {
+                if let Some(rect) = area.intersection(&self.chunk_area(chunk_x, chunk_y)) {
+                    let chunk_index = self.chunk_index(chunk_x, chunk_y);
+                    self.chunks[chunk_index].wake(rect);
+                }
+            }
+        }
+    }
+
+    /// Cells of the chunk at the given chunk coordinates.
+    fn chunk_area(&self, chunk_x: usize, chunk_y: usize) -> DirtyRect {
+        DirtyRect::new(
+            WorldPosition::new(
+                self.origin.x + chunk_x * CHUNK_SIZE,
+                self.origin.y + chunk_y * CHUNK_SIZE,
+            ),
+            WorldPosition::new(
+                self.origin.x + ((chunk_x + 1) * CHUNK_SIZE).min(self.width) - 1,
+                self.origin.y + ((chunk_y + 1) * CHUNK_SIZE).min(self.height) - 1,
+            ),
+        )
+    }
+
+    fn get_horizontal_voxels(
+        &self,
+        world_position: WorldPosition,
+        direction: isize,
+        range: usize,
+    ) -> Vec<(Option<Voxel>, WorldPosition)> {
+        (1..=range as isize)
+            .map(|distance| self.get_relative_voxel(world_position, direction * distance, 0))
+            .take_while(|(maybe_voxel, _)| *maybe_voxel != Some(Voxel::OOB))
+            .collect()
+    }
+
+    /// Voxel `dx` columns to the right and `dy` rows above `world_position`.
+    pub fn get_relative_voxel(
+        &self,
+        world_position: WorldPosition,
+        dx: isize,
+        dy: isize,
+    ) -> (Option<Voxel>, WorldPosition) {
+        match world_position.offset(dx, dy) {
+            Some(new_world_position) => (self.get_cell(&new_world_position), new_world_position),
+            None => (Some(Voxel::OOB), world_position),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{CpuBackend, SimulationBackend};
+    use crate::test_utils::voxel;
+
+    #[test]
+    fn cells_outside_of_the_map_are_out_of_bounds() {
+        let map = GameMap::new(2, 2);
+
+        assert_eq!(map.get_cell(&WorldPosition::new(2, 0)), Some(Voxel::OOB));
+        assert_eq!(
+            map.get_bottom_voxel(WorldPosition::new(0, 0)).0,
+            Some(Voxel::OOB)
+        );
+        assert_eq!(map.get_cell(&WorldPosition::new(1, 1)), None);
+    }
+
+    #[test]
+    fn changes_are_reported_once_and_then_cleared() {
+        let mut map = GameMap::new(3, 3);
+        map.set_cell(&WorldPosition::new(1, 2), &voxel("sand"));
+        map.step();
+        map.step();
+
+        let mut changes = map.take_changes();
+        changes.sort_by_key(|p| (p.y, p.x));
+        assert_eq!(
+            changes,
+            vec![
+                WorldPosition::new(1, 0),
+                WorldPosition::new(1, 1),
+                WorldPosition::new(1, 2)
+            ]
+        );
+        assert!(map.take_changes().is_empty());
+    }
+
+    #[test]
+    fn sand_column_piles_up_on_the_floor() {
+        let mut map = GameMap::new(1, 4);
+        map.set_cell(&WorldPosition::new(0, 3), &voxel("sand"));
+        map.set_cell(&WorldPosition::new(0, 2), &voxel("sand"));
+
+        while map.step() > 0 {}
+
+        assert!(map.get_cell(&WorldPosition::new(0, 0)).is_some());
+        assert!(map.get_cell(&WorldPosition::new(0, 1)).is_some());
+        assert_eq!(map.get_cell(&WorldPosition::new(0, 2)), None);
+        assert_eq!(map.get_cell(&WorldPosition::new(0, 3)), None);
+    }
+
+    /// Name of the element at `world_position`, whatever the state of the voxel.
+    fn element(map: &GameMap, world_position: WorldPosition) -> Option<&str> {
+        match map.get_cell(&world_position) {
+            Some(Voxel::of { data }) => Some(&map.registry().get(data.element).name),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn sand_sinks_through_a_column_of_water() {
+        let mut map = GameMap::new(1, 4);
+        for y in 0..3 {
+            map.set_cell(&WorldPosition::new(0, y), &voxel("water"));
+        }
+        map.set_cell(&WorldPosition::new(0, 3), &voxel("sand"));
+
+        for _ in 0..3 {
+            map.step();
+        }
+
+        assert_eq!(element(&map, WorldPosition::new(0, 0)), Some("sand"));
+        for y in 1..4 {
+            assert_eq!(element(&map, WorldPosition::new(0, y)), Some("water"));
+        }
+    }
+
+    #[test]
+    fn sand_poured_in_a_pool_ends_up_under_the_water() {
+        let mut map = GameMap::new(3, 6);
+        for x in 0..3 {
+            map.set_cell(&WorldPosition::new(x, 0), &voxel("water"));
+            map.set_cell(&WorldPosition::new(x, 1), &voxel("water"));
+        }
+        for y in 3..6 {
+            map.set_cell(&WorldPosition::new(1, y), &voxel("sand"));
+        }
+
+        for _ in 0..20 {
+            map.step();
+        }
+
+        let count = |y: usize, name: &str| {
+            (0..3)
+                .filter(|x| map.get_cell(&WorldPosition::new(*x, y)) == Some(voxel(name)))
+                .count()
+        };
+        assert_eq!(count(0, "sand"), 3);
+        assert_eq!(count(1, "water"), 3);
+        assert_eq!(count(2, "water"), 3);
+    }
+
+    #[test]
+    fn voxels_move_at_most_once_per_tick() {
+        let mut map = GameMap::new(20, 1);
+        map.set_cell(&WorldPosition::new(0, 0), &voxel("water"));
+        let dispersion = map.registry().get(Element(1)).dispersion;
+
+        map.step();
+
+        assert_eq!(
+            map.get_cell(&WorldPosition::new(dispersion, 0)),
+            Some(voxel("water"))
+        );
+        assert_eq!(map.tick(), 1);
+    }
+
+    fn velocity(map: &GameMap, world_position: WorldPosition) -> Option<f32> {
+        match map.get_cell(&world_position) {
+            Some(Voxel::of { data }) => Some(data.velocity),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn fast_voxels_travel_several_cells_per_tick() {
+        let mut map = GameMap::new(1, 5);
+        let mut voxel = voxel("sand");
+        if let Voxel::of { data } = &mut voxel {
+            data.velocity = 3.;
+        }
+        map.set_cell(&WorldPosition::new(0, 4), &voxel);
+
+        map.step();
+        assert_eq!(velocity(&map, WorldPosition::new(0, 1)), Some(3.25));
+        map.step();
+        assert_eq!(velocity(&map, WorldPosition::new(0, 0)), Some(0.));
+    }
+
+    #[test]
+    fn falling_voxels_speed_up_to_their_terminal_velocity() {
+        let mut map = GameMap::new(1, 200);
+        map.set_cell(&WorldPosition::new(0, 199), &voxel("sand"));
+        let speed = map
+            .registry()
+            .get(map.registry().find("sand").unwrap())
+            .speed;
+
+        for _ in 0..40 {
+            map.step();
+        }
+
+        let (position, data) = map.iter().next().unwrap();
+        assert!(199 - position.y > 40);
+        assert_eq!(data.velocity, speed);
+    }
+
+    /// Rows of the map from the top, one letter per voxel: the first one of its
+    /// element name.
+    fn layout(map: &GameMap) -> Vec<String> {
+        (0..map.height)
+            .rev()
+            .map(|y| {
+                (0..map.width)
+                    .map(|x| match map.get_cell(&WorldPosition::new(x, y)) {
+                        Some(Voxel::of { data }) => {
+                            map.registry().get(data.element).name.as_bytes()[0] as char
+                        }
+                        _ => '.',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_map_gives_same_result() {
+        let mut cells = Vec::new();
+        for x in 2..6 {
+            cells.push((WorldPosition::new(x, 7), "water"));
+            cells.push((WorldPosition::new(x, 6), "sand"));
+            cells.push((WorldPosition::new(x, 5), "earth"));
+        }
+        let mut first = GameMap::new(8, 8);
+        for (position, name) in &cells {
+            first.set_cell(position, &voxel(name));
+        }
+        // Same cells painted in another order, after a voxel that is erased again
+        let mut second = GameMap::new(8, 8);
+        second.set_cell(&WorldPosition::new(0, 0), &voxel("stone"));
+        for (position, name) in cells.iter().rev() {
+            second.set_cell(position, &voxel(name));
+        }
+        second.delete_cell(&WorldPosition::new(0, 0));
+        second.take_changes();
+
+        for _ in 0..30 {
+            first.step();
+        }
+        CpuBackend.run(&mut second, 30).unwrap();
+
+        assert_eq!(first.cells, second.cells);
+        assert_eq!(
+            layout(&first),
+            vec![
+                "........", "........", "........", "........", "........", "........", "w..ssw..",
+                "wseeeesw",
+            ]
+        );
+    }
+
+    #[test]
+    fn oil_floats_on_water_and_sand_sinks_through_both() {
+        let mut map = GameMap::new(1, 6);
+        map.set_cell(&WorldPosition::new(0, 0), &voxel("oil"));
+        map.set_cell(&WorldPosition::new(0, 1), &voxel("oil"));
+        map.set_cell(&WorldPosition::new(0, 2), &voxel("water"));
+        map.set_cell(&WorldPosition::new(0, 3), &voxel("water"));
+        map.set_cell(&WorldPosition::new(0, 5), &voxel("sand"));
+
+        for _ in 0..20 {
+            map.step();
+        }
+
+        let column: Vec<_> = (0..6)
+            .map(|y| map.get_cell(&WorldPosition::new(0, y)))
+            .collect();
+        assert_eq!(
+            column,
+            vec![
+                Some(voxel("sand")),
+                Some(voxel("water")),
+                Some(voxel("water")),
+                Some(voxel("oil")),
+                Some(voxel("oil")),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn horizontal_queries_stop_at_the_border() {
+        let map = GameMap::new(4, 1);
+
+        let left = map.get_left_voxels(WorldPosition::new(1, 0), 3);
+        let right = map.get_right_voxels(WorldPosition::new(1, 0), 3);
+
+        assert_eq!(left, vec![(None, WorldPosition::new(0, 0))]);
+        assert_eq!(
+            right,
+            vec![
+                (None, WorldPosition::new(2, 0)),
+                (None, WorldPosition::new(3, 0))
+            ]
+        );
+    }
+
+    #[test]
+    fn communicating_vessels_reach_the_same_level() {
+        let registry = ElementRegistry::from_ron(
+            r#"[
+                (name: "water", color: (0., 0., 1.), kind: Liquid, density: 1.,
+                 dispersion: 8, moves: [(0, -1), (-1, -1), (1, -1), (-1, 0), (1, 0)]),
+                (name: "wall", color: (1., 1., 1.), kind: Solid, density: 5., moves: []),
+            ]"#,
+        )
+        .unwrap();
+        let water = registry.spawn_voxel(registry.find("water").unwrap());
+        let wall = registry.spawn_voxel(registry.find("wall").unwrap());
+        let mut map = GameMap::with_registry(5, 7, registry);
+        for x in 0..5 {
+            map.set_cell(&WorldPosition::new(x, 0), &water);
+        }
+        for y in 1..7 {
+            map.set_cell(&WorldPosition::new(2, y), &wall);
+        }
+        for y in 1..5 {
+            map.set_cell(&WorldPosition::new(0, y), &water);
+            map.set_cell(&WorldPosition::new(1, y), &water);
+        }
+
+        for _ in 0..50 {
+            map.step();
+        }
+
+        let level = |x: usize| {
+            (1..7)
+                .filter(|y| map.get_cell(&WorldPosition::new(x, *y)) == Some(water))
+                .count()
+        };
+        // Both sides settle two cells high
+        assert_eq!([level(0), level(1), level(3), level(4)], [2; 4]);
+    }
+
+    #[test]
+    fn top_neighbours_are_above() {
+        let mut map = GameMap::new(3, 2);
+        map.set_cell(&WorldPosition::new(0, 1), &voxel("sand"));
+
+        assert_eq!(
+            map.get_top_left_voxel(WorldPosition::new(1, 0)),
+            (Some(voxel("sand")), WorldPosition::new(0, 1))
+        );
+        assert_eq!(
+            map.get_top_voxel(WorldPosition::new(1, 0)),
+            (None, WorldPosition::new(1, 1))
+        );
+        assert_eq!(
+            map.get_top_right_voxel(WorldPosition::new(1, 1)).0,
+            Some(Voxel::OOB)
+        );
+    }
+
+    #[test]
+    fn smoke_rises_and_dissipates() {
+        let mut map = GameMap::new(5, 20);
+        let smoke = voxel("smoke");
+        map.set_cell(&WorldPosition::new(2, 0), &smoke);
+
+        for _ in 0..10 {
+            map.step();
+        }
+        let height = map.iter().map(|(position, _)| position.y).max().unwrap();
+        assert!(height > 3, "smoke only rose to {}", height);
+
+        let lifetime = match smoke {
+            Voxel::of { data } => data.lifetime.unwrap(),
+            Voxel::OOB => unreachable!(),
+        };
+        for _ in 10..=lifetime {
+            map.step();
+        }
+        assert_eq!(map.iter().count(), 0);
+    }
+
+    #[test]
+    fn sand_piles_up_on_a_floating_stone_platform() {
+        let mut map = GameMap::new(3, 6);
+        for x in 0..3 {
+            map.set_cell(&WorldPosition::new(x, 3), &voxel("stone"));
+        }
+        map.set_cell(&WorldPosition::new(1, 5), &voxel("sand"));
+
+        for _ in 0..5 {
+            map.step();
+        }
+
+        assert_eq!(
+            map.get_cell(&WorldPosition::new(1, 3)),
+            Some(voxel("stone"))
+        );
+        assert_eq!(map.get_cell(&WorldPosition::new(1, 4)), Some(voxel("sand")));
+    }
+
+    fn with_temperature(mut voxel: Voxel, temperature: f32) -> Voxel {
+        if let Voxel::of { data } = &mut voxel {
+            data.temperature = temperature;
+        }
+        voxel
+    }
+
+    fn temperature(map: &GameMap, world_position: WorldPosition) -> f32 {
+        match map.get_cell(&world_position) {
+            Some(Voxel::of { data }) => data.temperature,
+            _ => panic!("no voxel at {:?}", world_position),
+        }
+    }
+
+    #[test]
+    fn heat_flows_from_hot_to_cold_voxels() {
+        let mut map = GameMap::new(2, 1);
+        map.set_cell(
+            &WorldPosition::new(0, 0),
+            &with_temperature(voxel("metal"), 100.),
+        );
+        map.set_cell(
+            &WorldPosition::new(1, 0),
+            &with_temperature(voxel("metal"), 0.),
+        );
+
+        map.step();
+
+        let (hot, cold) = (
+            temperature(&map, WorldPosition::new(0, 0)),
+            temperature(&map, WorldPosition::new(1, 0)),
+        );
+        assert!(hot < 100. && cold > 0.);
+        assert!((hot + cold - 100.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn heat_is_kept_across_sleeping_chunks() {
+        let mut map = GameMap::new(2 * CHUNK_SIZE, 1);
+        for x in 0..2 * CHUNK_SIZE {
+            map.set_cell(&WorldPosition::new(x, 0), &voxel("metal"));
+        }
+        while map.awake_chunks() > 0 {
+            map.step();
+        }
+        // Heat spreads from the edge of the left chunk into the sleeping right one
+        map.set_cell(
+            &WorldPosition::new(CHUNK_SIZE - 2, 0),
+            &with_temperature(voxel("metal"), 1000.),
+        );
+        let total = |map: &GameMap| map.iter().map(|(_, data)| data.temperature).sum::<f32>();
+        let before = total(&map);
+
+        for _ in 0..100 {
+            map.step();
+        }
+
+        assert!(temperature(&map, WorldPosition::new(CHUNK_SIZE + 2, 0)) > 30.);
+        assert!((total(&map) - before).abs() < 0.1);
+    }
+
+    #[test]
+    fn water_next_to_lava_boils_into_steam() {
+        let mut map = GameMap::new(2, 1);
+        map.set_cell(&WorldPosition::new(0, 0), &voxel("lava"));
+        map.set_cell(&WorldPosition::new(1, 0), &voxel("water"));
+
+        for _ in 0..5 {
+            map.step();
+        }
+
+        let steam = map.registry().find("steam").unwrap();
+        assert!(map.iter().any(|(_, data)| data.element == steam));
+    }
+
+    #[test]
+    fn lava_cools_into_stone_in_the_air() {
+        let mut map = GameMap::new(3, 3);
+        map.set_cell(&WorldPosition::new(1, 0), &voxel("lava"));
+
+        for _ in 0..1000 {
+            map.step();
+        }
+
+        let stone = map.registry().find("stone").unwrap();
+        assert!(map.iter().all(|(_, data)| data.element == stone));
+        assert_eq!(map.iter().count(), 1);
+    }
+
+    #[test]
+    fn cold_water_freezes_into_ice() {
+        let mut map = GameMap::new(1, 1);
+        map.set_cell(
+            &WorldPosition::new(0, 0),
+            &with_temperature(voxel("water"), -5.),
+        );
+
+        map.step();
+
+        let ice = map.registry().find("ice").unwrap();
+        assert_eq!(map.iter().next().unwrap().1.element, ice);
+    }
+
+    #[test]
+    fn reactions_turn_both_voxels_into_their_products() {
+        let registry = ElementRegistry::from_ron(
+            r#"[
+                (name: "acid", color: (0., 1., 0.), kind: Static, density: 1., moves: [],
+                 reactions: [(with: "metal", into: Some("acid"), other_into: None, probability: 1.0)]),
+                (name: "metal", color: (1., 1., 1.), kind: Static, density: 5., moves: [],
+                 reactions: [(with: "acid", into: Some("rust"), other_into: None, probability: 0.0)]),
+                (name: "rust", color: (1., 0., 0.), kind: Static, density: 5., moves: []),
+            ]"#,
+        )
+        .unwrap();
+        let acid = registry.spawn_voxel(registry.find("acid").unwrap());
+        let metal = registry.spawn_voxel(registry.find("metal").unwrap());
+        let mut map = GameMap::with_registry(3, 1, registry);
+        map.set_cell(&WorldPosition::new(0, 0), &acid);
+        map.set_cell(&WorldPosition::new(1, 0), &metal);
+        map.set_cell(&WorldPosition::new(2, 0), &metal);
+
+        map.step();
+        map.step();
+
+        assert_eq!(element(&map, WorldPosition::new(0, 0)), Some("acid"));
+        assert_eq!(map.get_cell(&WorldPosition::new(1, 0)), None);
+        assert_eq!(map.get_cell(&WorldPosition::new(2, 0)), Some(metal));
+    }
+
+    #[test]
+    fn voxels_keep_their_shade_as_they_fall() {
+        let mut map = GameMap::new(8, 8);
+        let sand = map.registry().find("sand").unwrap();
+        for x in 0..8 {
+            map.spawn_cell(&WorldPosition::new(x, 7), sand);
+        }
+        let shades = |map: &GameMap| map.iter().map(|(_, data)| data.shade).collect::<Vec<_>>();
+        let spawned = shades(&map);
+        assert!(spawned.iter().any(|shade| *shade != spawned[0]));
+
+        for _ in 0..10 {
+            map.step();
+        }
+
+        assert!(map.iter().all(|(world_position, _)| world_position.y == 0));
+        assert_eq!(shades(&map), spawned);
+    }
+
+    fn burning(mut voxel: Voxel) -> Voxel {
+        if let Voxel::of { data } = &mut voxel {
+            data.burning = true;
+            data.lifetime = Some(ElementRegistry::default().get(data.element).burn_time);
+        }
+        voxel
+    }
+
+    #[test]
+    fn fire_spreads_through_wood_and_leaves_flames_and_smoke() {
+        let mut map = GameMap::new(6, 20);
+        for x in 0..5 {
+            map.set_cell(&WorldPosition::new(x, 0), &voxel("wood"));
+        }
+        map.set_cell(&WorldPosition::new(0, 0), &burning(voxel("wood")));
+        map.set_cell(&WorldPosition::new(5, 0), &voxel("stone"));
+
+        let registry = map.registry().clone();
+        let wood = registry.find("wood").unwrap();
+        let smoke = registry.find("smoke").unwrap();
+        let fire = registry.find("fire").unwrap();
+        let (mut smoked, mut flamed) = (false, false);
+        for _ in 0..5000 {
+            map.step();
+            smoked |= map.iter().any(|(_, data)| data.element == smoke);
+            flamed |= map.iter().any(|(_, data)| data.element == fire);
+        }
+
+        assert!(smoked && flamed);
+        assert!(map.iter().all(|(_, data)| data.element != wood));
+        assert!(matches!(
+            map.get_cell(&WorldPosition::new(5, 0)),
+            Some(Voxel::of { data }) if data.element == registry.find("stone").unwrap() && !data.burning
+        ));
+    }
+
+    #[test]
+    fn cold_voxels_do_not_set_fire() {
+        let mut map = GameMap::new(2, 1);
+        map.set_cell(&WorldPosition::new(0, 0), &voxel("sand"));
+        map.set_cell(&WorldPosition::new(1, 0), &voxel("wood"));
+
+        for _ in 0..100 {
+            map.step();
+        }
+
+        assert_eq!(map.get_cell(&WorldPosition::new(1, 0)), Some(voxel("wood")));
+    }
+
+    #[test]
+    fn settled_chunks_fall_asleep() {
+        let mut map = GameMap::new(2 * CHUNK_SIZE, CHUNK_SIZE);
+        for y in 10..20 {
+            map.set_cell(&WorldPosition::new(5, y), &voxel("sand"));
+        }
+        assert_eq!(map.awake_chunks(), 1);
+
+        for _ in 0..200 {
+            map.step();
+        }
+
+        assert_eq!(map.awake_chunks(), 0);
+        assert_eq!(map.step(), 0);
+        assert_eq!(map.iter().count(), 10);
+    }
+
+    #[test]
+    fn changes_wake_neighbouring_chunks_up() {
+        let mut map = GameMap::new(2 * CHUNK_SIZE, 2 * CHUNK_SIZE);
+        map.set_cell(&WorldPosition::new(0, 0), &voxel("stone"));
+        assert!(!map.is_awake(&WorldPosition::new(CHUNK_SIZE, 0)));
+
+        map.set_cell(&WorldPosition::new(CHUNK_SIZE - 1, 0), &voxel("stone"));
+
+        assert!(map.is_awake(&WorldPosition::new(CHUNK_SIZE, 0)));
+        assert!(!map.is_awake(&WorldPosition::new(0, CHUNK_SIZE)));
+        assert_eq!(map.awake_chunks(), 2);
+    }
+
+    #[test]
+    fn voxels_about_to_react_stay_awake() {
+        let registry = ElementRegistry::from_ron(
+            r#"[
+                (name: "acid", color: (0., 1., 0.), kind: Static, density: 1., moves: [],
+                 reactions: [(with: "metal", into: None, other_into: None, probability: 0.05)]),
+                (name: "metal", color: (1., 1., 1.), kind: Static, density: 5., moves: []),
+            ]"#,
+        )
+        .unwrap();
+        let acid = registry.spawn_voxel(registry.find("acid").unwrap());
+        let metal = registry.spawn_voxel(registry.find("metal").unwrap());
+        let mut map = GameMap::with_registry(2, 1, registry);
+        map.set_cell(&WorldPosition::new(0, 0), &acid);
+        map.set_cell(&WorldPosition::new(1, 0), &metal);
+
+        for _ in 0..500 {
+            map.step();
+        }
+
+        assert_eq!(map.iter().count(), 0);
+        assert_eq!(map.awake_chunks(), 0);
+    }
+
+    #[test]
+    fn chunks_updated_in_parallel_keep_every_voxel() {
+        let build = || {
+            let mut map = GameMap::new(4 * CHUNK_SIZE, 2 * CHUNK_SIZE);
+            for x in (0..4 * CHUNK_SIZE).step_by(3) {
+                for y in CHUNK_SIZE - 4..CHUNK_SIZE + 4 {
+                    let name = if x % 2 == 0 { "sand" } else { "water" };
+                    map.set_cell(&WorldPosition::new(x, y), &voxel(name));
+                }
+            }
+            map
+        };
+        let mut first = build();
+        let mut second = build();
+        let count = first.iter().count();
+
+        for _ in 0..100 {
+            first.step();
+            second.step();
+        }
+
+        assert_eq!(first.iter().count(), count);
+        assert!(first.iter().all(|(position, _)| position.y < CHUNK_SIZE));
+        assert!(first.iter().eq(second.iter()));
+    }
+
+    /// Steps `map` like [`GameMap::step`], updating the chunks in place one after
+    /// the other instead of on copies of their tiles.
+    fn step_without_tiles(map: &mut GameMap) -> usize {
+        map.generation += 1;
+        map.chunks.iter_mut().for_each(Chunk::start_tick);
+        let mut moved = 0;
+        for pass in 0..4 {
+            for chunk_index in 0..map.chunks.len() {
+                let chunk_x = chunk_index % map.chunks_width;
+                let chunk_y = chunk_index / map.chunks_width;
+                if chunk_x % 2 + 2 * (chunk_y % 2) != pass {
+                    continue;
+                }
+                if let Some(dirty) = map.chunks[chunk_index].dirty {
+                    moved += map.update_rect(dirty);
+                }
+            }
+        }
+        map.update_temperatures();
+        map.tick += 1;
+        moved
+    }
+
+    #[test]
+    fn tiles_update_like_the_whole_map() {
+        let registry = ElementRegistry::default();
+        let names = registry
+            .iter()
+            .map(|(_, definition)| definition.name.clone())
+            .collect::<Vec<_>>();
+        let mut tiles = GameMap::new(4 * CHUNK_SIZE, 3 * CHUNK_SIZE);
+        tiles.seed = 3;
+        // Around the edges of the chunks and of their tiles
+        let mut i = 0;
+        for edge in (0..4 * CHUNK_SIZE).step_by(TILE_MARGIN) {
+            for x in edge.saturating_sub(2)..(edge + 2).min(tiles.width) {
+                for y in (0..tiles.height).filter(|y| (y + x) % 3 != 0) {
+                    i += 1;
+                    tiles.set_cell(&WorldPosition::new(x, y), &voxel(&names[i % names.len()]));
+                }
+            }
+        }
+        let mut whole = tiles.clone();
+
+        for tick in 0..100 {
+            assert_eq!(
+                tiles.step(),
+                step_without_tiles(&mut whole),
+                "tick {}",
+                tick
+            );
+            assert_eq!(tiles.woken_chunks(), whole.woken_chunks(), "tick {}", tick);
+            assert!(tiles.iter().eq(whole.iter()), "tick {}", tick);
+        }
+    }
+
+    #[test]
+    fn water_spreads_on_the_floor() {
+        let mut map = GameMap::new(3, 3);
+        map.set_cell(&WorldPosition::new(1, 0), &voxel("water"));
+        map.set_cell(&WorldPosition::new(1, 1), &voxel("water"));
+
+        for _ in 0..5 {
+            map.step();
+        }
+
+        let floor = (0..3)
+            .filter(|x| map.get_cell(&WorldPosition::new(*x, 0)).is_some())
+            .count();
+        assert_eq!(floor, 2);
+        assert_eq!(map.get_cell(&WorldPosition::new(1, 1)), None);
+    }
+}
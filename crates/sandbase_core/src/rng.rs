@@ -0,0 +1,77 @@
+/// Small deterministic random number generator (SplitMix64).
+///
+/// The simulation seeds one per voxel and per tick from the map seed, so random
+/// behaviours don't depend on the order voxels are updated in.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    /// Generator for the voxel at `(x, y)` during `tick`.
+    pub fn for_cell(seed: u64, tick: u64, x: usize, y: usize) -> Self {
+        let mut rng = Rng::new(seed ^ tick.wrapping_mul(0xA076_1D64_78BD_642F));
+        rng.state ^= (x as u64).wrapping_mul(0xE703_7ED1_A0B4_28DB);
+        rng.state ^= (y as u64).wrapping_mul(0x8EBC_6AF0_9C88_C6E3);
+        rng.next_u64();
+        rng
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform value in `0..bound`, `bound` must not be 0.
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    /// Uniform value in `[0, 1)`.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Returns true with the given probability.
+    pub fn chance(&mut self, probability: f32) -> bool {
+        self.next_f32() < probability
+    }
+
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_cell_and_tick_give_the_same_numbers() {
+        let mut first = Rng::for_cell(42, 7, 3, 4);
+        let mut second = Rng::for_cell(42, 7, 3, 4);
+        let mut neighbour = Rng::for_cell(42, 7, 4, 4);
+
+        let a = first.next_u64();
+        assert_eq!(a, second.next_u64());
+        assert_ne!(a, neighbour.next_u64());
+    }
+
+    #[test]
+    fn floats_stay_in_the_unit_range() {
+        let mut rng = Rng::new(1);
+
+        assert!((0..1000)
+            .map(|_| rng.next_f32())
+            .all(|f| (0. ..1.).contains(&f)));
+    }
+}
@@ -0,0 +1,633 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::chunk::{DirtyRect, CHUNK_SIZE};
+use crate::elements::{Element, ElementRegistry};
+use crate::map::{GameMap, MAX_CELLS};
+use crate::streaming::StoredColumn;
+use crate::terrain::TerrainConfig;
+use crate::voxels::VoxelStruct;
+use crate::world_position::WorldPosition;
+
+/// First bytes of every save file.
+const MAGIC: &[u8; 4] = b"SBSV";
+
+/// Version written by [`save_world`]. Bump it whenever the layout changes and keep
+/// reading the older versions in [`load_world`].
+///
+/// - 1: first version
+/// - 2: voxels carry their shade
+/// - 3: the chunk columns out of the map
+pub const FORMAT_VERSION: u16 = 3;
+
+/// Cell tags of the run-length encoded cells.
+const EMPTY_CELL: u8 = 0;
+const VOXEL_CELL: u8 = 1;
+
+/// Everything saved along with the map to put the player back where they were.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorldInfo {
+    /// Size of a voxel on screen, in pixels.
+    pub px_per_voxel: u32,
+    /// World column of the leftmost chunk column of the map.
+    pub first_column: i64,
+    /// Horizontal translation of the camera, in pixels.
+    pub camera_x: f32,
+    /// Horizontal viewpoint of the player, in pixels.
+    pub viewpoint_x: u32,
+    /// Terrain generated for the chunk columns not visited yet.
+    pub terrain: TerrainConfig,
+    /// Visited chunk columns out of the map, see
+    /// [`StreamedWorld::stored_columns`](crate::streaming::StreamedWorld::stored_columns).
+    pub columns: Vec<StoredColumn>,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    /// The file doesn't start like a save file.
+    NotASave,
+    /// The file was written by a newer version of the game.
+    UnsupportedVersion(u16),
+    /// The file holds an element missing from the registry.
+    UnknownElement(String),
+    /// The file is truncated or damaged.
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Io(e) => write!(f, "{}", e),
+            SaveError::NotASave => write!(f, "not a save file"),
+            SaveError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save format version {}", version)
+            }
+            SaveError::UnknownElement(name) => write!(f, "unknown element {:?}", name),
+            SaveError::Corrupt(reason) => write!(f, "corrupt save: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {}
+
+impl From<io::Error> for SaveError {
+    fn from(e: io::Error) -> Self {
+        SaveError::Io(e)
+    }
+}
+
+/// Writes the whole simulation state: cells, tick, seed and the chunks to update
+/// next, so that loading it back goes on exactly as if it was never saved.
+///
+/// Elements are written by name, identical neighbouring cells are written once
+/// with their count.
+pub fn save_world(
+    writer: &mut impl Write,
+    map: &GameMap,
+    info: &WorldInfo,
+) -> Result<(), SaveError> {
+    let mut writer = Writer(writer);
+    writer.0.write_all(MAGIC)?;
+    writer.u16(FORMAT_VERSION)?;
+
+    writer.u32(info.px_per_voxel)?;
+    writer.i64(info.first_column)?;
+    writer.f32(info.camera_x)?;
+    writer.u32(info.viewpoint_x)?;
+    write_terrain(&mut writer, &info.terrain)?;
+
+    writer.u32(map.width as u32)?;
+    writer.u32(map.height as u32)?;
+    writer.u64(map.tick())?;
+    writer.u64(map.seed)?;
+    writer.f32(map.ambient_temperature)?;
+
+    let registry = map.registry();
+    writer.u16(registry.len() as u16)?;
+    for (_, definition) in registry.iter() {
+        writer.str(&definition.name)?;
+    }
+
+    for rect in map.woken_chunks() {
+        match rect {
+            Some(rect) => {
+                writer.u8(1)?;
+                for value in [rect.min.x, rect.min.y, rect.max.x, rect.max.y] {
+                    writer.u32(value as u32)?;
+                }
+            }
+            None => writer.u8(0)?,
+        }
+    }
+
+    write_cells(&mut writer, map.cells())?;
+
+    writer.u32(info.columns.len() as u32)?;
+    for (column, cells) in &info.columns {
+        writer.i64(*column)?;
+        write_cells(&mut writer, cells)?;
+    }
+    Ok(())
+}
+
+/// Reads a world written by [`save_world`], by this version or an older one.
+///
+/// Elements are matched by name against `registry`, so saves keep loading when
+/// elements are added to or reordered in the definitions, or removed from them
+/// while no cell holds them.
+pub fn load_world(
+    reader: &mut impl Read,
+    registry: ElementRegistry,
+) -> Result<(GameMap, WorldInfo), SaveError> {
+    let mut reader = Reader(reader);
+    let mut magic = [0; 4];
+    reader.0.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(SaveError::NotASave);
+    }
+    match reader.u16()? {
+        version @ 1..=FORMAT_VERSION => load_version(&mut reader, registry, version),
+        version => Err(SaveError::UnsupportedVersion(version)),
+    }
+}
+
+fn load_version(
+    reader: &mut Reader<impl Read>,
+    registry: ElementRegistry,
+    version: u16,
+) -> Result<(GameMap, WorldInfo), SaveError> {
+    let mut info = WorldInfo {
+        px_per_voxel: reader.u32()?,
+        first_column: reader.i64()?,
+        camera_x: reader.f32()?,
+        viewpoint_x: reader.u32()?,
+        terrain: read_terrain(reader)?,
+        columns: Vec::new(),
+    };
+
+    let width = reader.u32()? as usize;
+    let height = reader.u32()? as usize;
+    let size = width
+        .checked_mul(height)
+        .filter(|size| *size <= MAX_CELLS)
+        .ok_or(SaveError::Corrupt("map too large"))?;
+    let tick = reader.u64()?;
+    let seed = reader.u64()?;
+    let ambient_temperature = reader.f32()?;
+
+    // Element ids of the file mapped to the ones of the current registry. Elements
+    // missing from it are only reported if a cell holds them.
+    let elements = (0..reader.u16()?)
+        .map(|_| {
+            let name = reader.str()?;
+            Ok(registry.find(&name).ok_or(name))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut map = GameMap::with_registry(width, height, registry);
+    map.seed = seed;
+    map.ambient_temperature = ambient_temperature;
+
+    let woken = (0..map.woken_chunks().len())
+        .map(|_| {
+            Ok(match reader.u8()? {
+                0 => None,
+                _ => {
+                    let mut values = [0; 4];
+                    for value in values.iter_mut() {
+                        *value = reader.u32()? as usize;
+                    }
+                    let [min_x, min_y, max_x, max_y] = values;
+                    if min_x > max_x || min_y > max_y || max_x >= width || max_y >= height {
+                        return Err(SaveError::Corrupt("woken chunk out of the map"));
+                    }
+                    Some(DirtyRect::new(
+                        WorldPosition::new(min_x, min_y),
+                        WorldPosition::new(max_x, max_y),
+                    ))
+                }
+            })
+        })
+        .collect::<Result<Vec<_>, SaveError>>()?;
+
+    let cells = read_cells(reader, &elements, version, size)?;
+    map.restore(tick, cells, &woken);
+
+    if version >= 3 {
+        let column_size = CHUNK_SIZE
+            .checked_mul(height)
+            .filter(|size| *size <= MAX_CELLS)
+            .ok_or(SaveError::Corrupt("map too large"))?;
+        for _ in 0..reader.u32()? {
+            let column = reader.i64()?;
+            let cells = read_cells(reader, &elements, version, column_size)?;
+            info.columns.push((column, cells));
+        }
+    }
+    Ok((map, info))
+}
+
+fn write_terrain(writer: &mut Writer<impl Write>, terrain: &TerrainConfig) -> io::Result<()> {
+    writer.u64(terrain.seed)?;
+    writer.f32(terrain.ground_level)?;
+    writer.f32(terrain.hill_height)?;
+    writer.f32(terrain.hill_width)?;
+    writer.u32(terrain.earth_depth as u32)?;
+    writer.f32(terrain.dune_height)?;
+    writer.f32(terrain.desert_width)?;
+    writer.f32(terrain.cave_size)?;
+    writer.f32(terrain.cave_density)?;
+    writer.f32(terrain.water_level)
+}
+
+fn read_terrain(reader: &mut Reader<impl Read>) -> io::Result<TerrainConfig> {
+    Ok(TerrainConfig {
+        seed: reader.u64()?,
+        ground_level: reader.f32()?,
+        hill_height: reader.f32()?,
+        hill_width: reader.f32()?,
+        earth_depth: reader.u32()? as usize,
+        dune_height: reader.f32()?,
+        desert_width: reader.f32()?,
+        cave_size: reader.f32()?,
+        cave_density: reader.f32()?,
+        water_level: reader.f32()?,
+    })
+}
+
+/// Writes `cells` as runs of identical cells.
+fn write_cells(writer: &mut Writer<impl Write>, cells: &[Option<VoxelStruct>]) -> io::Result<()> {
+    let mut start = 0;
+    while start < cells.len() {
+        let run = cells[start..]
+            .iter()
+            .take_while(|cell| **cell == cells[start])
+            .count();
+        writer.u32(run as u32)?;
+        write_cell(writer, &cells[start])?;
+        start += run;
+    }
+    Ok(())
+}
+
+/// Reads `count` cells written by [`write_cells`].
+fn read_cells(
+    reader: &mut Reader<impl Read>,
+    elements: &[Result<Element, String>],
+    version: u16,
+    count: usize,
+) -> Result<Vec<Option<VoxelStruct>>, SaveError> {
+    let mut cells = Vec::with_capacity(count);
+    while cells.len() < count {
+        let run = reader.u32()? as usize;
+        let cell = read_cell(reader, elements, version)?;
+        if run == 0 || cells.len() + run > count {
+            return Err(SaveError::Corrupt("cell runs don't match the map size"));
+        }
+        cells.resize(cells.len() + run, cell);
+    }
+    Ok(cells)
+}
+
+fn write_cell(writer: &mut Writer<impl Write>, cell: &Option<VoxelStruct>) -> io::Result<()> {
+    let data = match cell {
+        Some(data) => data,
+        None => return writer.u8(EMPTY_CELL),
+    };
+    writer.u8(VOXEL_CELL)?;
+    writer.u16(data.element.0)?;
+    writer.f32(data.velocity)?;
+    match data.lifetime {
+        Some(lifetime) => {
+            writer.u8(1)?;
+            writer.u32(lifetime)?;
+        }
+        None => writer.u8(0)?,
+    }
+    writer.f32(data.temperature)?;
+    writer.u8(data.burning as u8)?;
+    writer.u8(data.shade)
+}
+
+fn read_cell(
+    reader: &mut Reader<impl Read>,
+    elements: &[Result<Element, String>],
+    version: u16,
+) -> Result<Option<VoxelStruct>, SaveError> {
+    match reader.u8()? {
+        EMPTY_CELL => Ok(None),
+        VOXEL_CELL => {
+            let element = match elements.get(reader.u16()? as usize) {
+                Some(Ok(element)) => *element,
+                Some(Err(name)) => return Err(SaveError::UnknownElement(name.clone())),
+                None => return Err(SaveError::Corrupt("element id out of the element table")),
+            };
+            let velocity = reader.f32()?;
+            let lifetime = match reader.u8()? {
+                0 => None,
+                _ => Some(reader.u32()?),
+            };
+            Ok(Some(VoxelStruct {
+                velocity,
+                element,
+                lifetime,
+                temperature: reader.f32()?,
+                burning: reader.u8()? != 0,
+                shade: if version >= 2 { reader.u8()? } else { 0 },
+            }))
+        }
+        _ => Err(SaveError::Corrupt("unknown cell tag")),
+    }
+}
+
+/// Little-endian encoding of the values of a save file.
+struct Writer<W>(W);
+
+impl<W: Write> Writer<W> {
+    fn u8(&mut self, value: u8) -> io::Result<()> {
+        self.0.write_all(&[value])
+    }
+
+    fn u16(&mut self, value: u16) -> io::Result<()> {
+        self.0.write_all(&value.to_le_bytes())
+    }
+
+    fn u32(&mut self, value: u32) -> io::Result<()> {
+        self.0.write_all(&value.to_le_bytes())
+    }
+
+    fn u64(&mut self, value: u64) -> io::Result<()> {
+        self.0.write_all(&value.to_le_bytes())
+    }
+
+    fn i64(&mut self, value: i64) -> io::Result<()> {
+        self.0.write_all(&value.to_le_bytes())
+    }
+
+    fn f32(&mut self, value: f32) -> io::Result<()> {
+        self.0.write_all(&value.to_le_bytes())
+    }
+
+    fn str(&mut self, value: &str) -> io::Result<()> {
+        self.u16(value.len() as u16)?;
+        self.0.write_all(value.as_bytes())
+    }
+}
+
+struct Reader<R>(R);
+
+impl<R: Read> Reader<R> {
+    fn bytes<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        self.0.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        self.bytes().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        self.bytes().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        self.bytes().map(u64::from_le_bytes)
+    }
+
+    fn i64(&mut self) -> io::Result<i64> {
+        self.bytes().map(i64::from_le_bytes)
+    }
+
+    fn f32(&mut self) -> io::Result<f32> {
+        self.bytes().map(f32::from_le_bytes)
+    }
+
+    fn str(&mut self) -> io::Result<String> {
+        let mut bytes = vec![0; self.u16()? as usize];
+        self.0.read_exact(&mut bytes)?;
+        String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elements::ElementDefinition;
+    use crate::test_utils::voxel;
+    use crate::voxels::Voxel;
+
+    fn info() -> WorldInfo {
+        WorldInfo {
+            px_per_voxel: 10,
+            first_column: -3,
+            camera_x: 42.5,
+            viewpoint_x: 40,
+            terrain: TerrainConfig {
+                seed: 9,
+                ..TerrainConfig::default()
+            },
+            columns: Vec::new(),
+        }
+    }
+
+    fn save(map: &GameMap) -> Vec<u8> {
+        save_with(map, &info())
+    }
+
+    fn save_with(map: &GameMap, info: &WorldInfo) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        save_world(&mut bytes, map, info).unwrap();
+        bytes
+    }
+
+    fn sand_only() -> ElementRegistry {
+        let sand = ElementRegistry::default()
+            .iter()
+            .map(|(_, definition)| definition.clone())
+            .filter(|definition| definition.name == "sand")
+            .collect();
+        ElementRegistry::new(sand).unwrap()
+    }
+
+    #[test]
+    fn loaded_worlds_carry_on_exactly_like_the_saved_one() {
+        let mut map = GameMap::new(40, 40);
+        map.seed = 5;
+        for x in 5..25 {
+            map.set_cell(&WorldPosition::new(x, 30), &voxel("sand"));
+            map.set_cell(&WorldPosition::new(x, 20), &voxel("water"));
+            map.set_cell(&WorldPosition::new(x, 10), &voxel("lava"));
+        }
+        for _ in 0..10 {
+            map.step();
+        }
+
+        let mut column = vec![None; CHUNK_SIZE * 40];
+        if let Voxel::of { data } = voxel("oil") {
+            column[7] = Some(data);
+        }
+        let info = WorldInfo {
+            columns: vec![(-4, column)],
+            ..info()
+        };
+
+        let bytes = save_with(&map, &info);
+        let (mut loaded, loaded_info) =
+            load_world(&mut bytes.as_slice(), ElementRegistry::default()).unwrap();
+
+        assert_eq!(loaded_info, info);
+        assert_eq!(loaded.tick(), map.tick());
+        for _ in 0..30 {
+            map.step();
+            loaded.step();
+        }
+        assert_eq!(loaded.cells(), map.cells());
+    }
+
+    #[test]
+    fn identical_cells_are_saved_once() {
+        let mut map = GameMap::new(64, 64);
+        for x in 0..64 {
+            map.set_cell(&WorldPosition::new(x, 0), &voxel("stone"));
+        }
+
+        assert!(save(&map).len() < 300);
+    }
+
+    #[test]
+    fn elements_are_matched_by_name_when_the_registry_changes() {
+        let mut map = GameMap::new(4, 4);
+        map.set_cell(&WorldPosition::new(1, 1), &voxel("water"));
+        let bytes = save(&map);
+        let mut elements = ElementRegistry::default()
+            .iter()
+            .map(|(_, definition)| definition.clone())
+            .collect::<Vec<ElementDefinition>>();
+        elements.reverse();
+        let registry = ElementRegistry::new(elements).unwrap();
+
+        let (loaded, _) = load_world(&mut bytes.as_slice(), registry).unwrap();
+
+        let (_, data) = loaded.iter().next().unwrap();
+        assert_eq!(loaded.registry().get(data.element).name, "water");
+    }
+
+    #[test]
+    fn missing_elements_are_reported() {
+        let mut map = GameMap::new(4, 4);
+        map.set_cell(&WorldPosition::new(1, 1), &voxel("water"));
+        let bytes = save(&map);
+        let registry = sand_only();
+
+        assert!(matches!(
+            load_world(&mut bytes.as_slice(), registry),
+            Err(SaveError::UnknownElement(name)) if name == "water"
+        ));
+    }
+
+    #[test]
+    fn unused_missing_elements_are_ignored() {
+        let mut map = GameMap::new(4, 4);
+        map.set_cell(&WorldPosition::new(1, 1), &voxel("sand"));
+        let bytes = save(&map);
+        let registry = sand_only();
+
+        let (loaded, _) = load_world(&mut bytes.as_slice(), registry).unwrap();
+
+        let (_, data) = loaded.iter().next().unwrap();
+        assert_eq!(loaded.registry().get(data.element).name, "sand");
+    }
+
+    #[test]
+    fn corrupt_saves_are_rejected() {
+        let bytes = save(&GameMap::new(40, 40));
+        // Width and height follow the magic, the version, the world info and the terrain
+        let size = 4 + 2 + 4 + 8 + 4 + 4 + (8 + 4 * 3 + 4 + 4 * 5);
+        let mut huge = bytes.clone();
+        huge[size..size + 8].fill(0xff);
+        // The first woken chunk follows the size, tick, seed, ambient temperature and
+        // the element table
+        let table: usize = ElementRegistry::default()
+            .iter()
+            .map(|(_, definition)| 2 + definition.name.len())
+            .sum();
+        let rect = size + 8 + 8 + 8 + 4 + 2 + table;
+        let mut outside = bytes[..rect].to_vec();
+        outside.push(1);
+        outside.extend([0; 8]);
+        outside.extend([0xff; 8]);
+
+        for bytes in [huge, outside] {
+            assert!(matches!(
+                load_world(&mut bytes.as_slice(), ElementRegistry::default()),
+                Err(SaveError::Corrupt(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn saves_of_older_versions_still_load() {
+        // 4×3 maps saved by versions 1 and 2, with sand, sand and water on the
+        // bottom row and the elements in another order than the registry
+        let fixtures: [(&[u8], u8); 2] = [
+            (include_bytes!("../tests/fixtures/save_v1.sbsv"), 0),
+            (include_bytes!("../tests/fixtures/save_v2.sbsv"), 3),
+        ];
+        for (bytes, sand_shade) in fixtures.iter().copied() {
+            let (map, loaded) = load_world(&mut &bytes[..], ElementRegistry::default()).unwrap();
+
+            assert_eq!(loaded, info());
+            assert_eq!((map.width, map.height), (4, 3));
+            assert_eq!((map.tick(), map.seed), (12, 5));
+            assert_eq!(map.ambient_temperature, 20.);
+            assert_eq!(
+                map.woken_chunks(),
+                vec![Some(DirtyRect::new(
+                    WorldPosition::new(0, 0),
+                    WorldPosition::new(3, 2)
+                ))]
+            );
+            let sand = match voxel("sand") {
+                Voxel::of { data } => VoxelStruct {
+                    shade: sand_shade,
+                    ..data
+                },
+                Voxel::OOB => unreachable!(),
+            };
+            let water = map.get_cell(&WorldPosition::new(2, 0));
+            assert!(matches!(
+                water,
+                Some(Voxel::of { data }) if data.element == map.registry().find("water").unwrap()
+                    && data.velocity == 1.5
+                    && data.shade == 0
+            ));
+            for x in 0..2 {
+                assert_eq!(
+                    map.get_cell(&WorldPosition::new(x, 0)),
+                    Some(Voxel::of { data: sand })
+                );
+            }
+            assert_eq!(map.iter().count(), 3);
+        }
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        assert!(matches!(
+            load_world(&mut b"not a save".as_slice(), ElementRegistry::default()),
+            Err(SaveError::NotASave)
+        ));
+        let mut bytes = save(&GameMap::new(4, 4));
+        bytes[4] = 99;
+        assert!(matches!(
+            load_world(&mut bytes.as_slice(), ElementRegistry::default()),
+            Err(SaveError::UnsupportedVersion(99))
+        ));
+    }
+}
@@ -0,0 +1,530 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::chunk::CHUNK_SIZE;
+use crate::elements::ElementRegistry;
+use crate::map::GameMap;
+use crate::voxels::VoxelStruct;
+
+/// Creates the chunk columns that were never visited, see
+/// [`GameMap::column_cells`] for the layout of the cells.
+pub trait ColumnGenerator: Send + Sync {
+    fn generate(
+        &self,
+        column: i64,
+        height: usize,
+        registry: &ElementRegistry,
+    ) -> Vec<Option<VoxelStruct>>;
+}
+
+/// World column and its cells, laid out like [`GameMap::column_cells`].
+pub type StoredColumn = (i64, Vec<Option<VoxelStruct>>);
+
+/// Generates empty columns.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct EmptyColumns;
+
+impl ColumnGenerator for EmptyColumns {
+    fn generate(&self, _: i64, height: usize, _: &ElementRegistry) -> Vec<Option<VoxelStruct>> {
+        vec![None; CHUNK_SIZE * height]
+    }
+}
+
+#[derive(Debug)]
+pub enum ChunkStoreError {
+    Io(io::Error),
+    Parse(ron::error::SpannedError),
+    Serialize(ron::Error),
+    /// A saved column holds an element missing from the registry.
+    UnknownElement(String),
+    /// The map width is not a whole number of chunk columns.
+    UnalignedWidth(usize),
+}
+
+impl fmt::Display for ChunkStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChunkStoreError::Io(e) => write!(f, "{}", e),
+            ChunkStoreError::Parse(e) => write!(f, "{}", e),
+            ChunkStoreError::Serialize(e) => write!(f, "{}", e),
+            ChunkStoreError::UnknownElement(name) => write!(f, "unknown element {:?}", name),
+            ChunkStoreError::UnalignedWidth(width) => write!(
+                f,
+                "map width {} is not a multiple of the chunk size {}",
+                width, CHUNK_SIZE
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ChunkStoreError {}
+
+impl From<io::Error> for ChunkStoreError {
+    fn from(e: io::Error) -> Self {
+        ChunkStoreError::Io(e)
+    }
+}
+
+impl From<ron::error::SpannedError> for ChunkStoreError {
+    fn from(e: ron::error::SpannedError) -> Self {
+        ChunkStoreError::Parse(e)
+    }
+}
+
+impl From<ron::Error> for ChunkStoreError {
+    fn from(e: ron::Error) -> Self {
+        ChunkStoreError::Serialize(e)
+    }
+}
+
+/// Content of a column file. Element ids index `elements`, the names of the
+/// registry the column was saved with.
+#[derive(Serialize, Deserialize)]
+struct ColumnFile<'a> {
+    elements: Vec<String>,
+    cells: Cow<'a, [Option<VoxelStruct>]>,
+}
+
+/// Directory holding the chunk columns unloaded from the map, one file each.
+/// Elements are saved by name, so columns keep loading when elements are added to
+/// or reordered in the definitions.
+#[derive(Clone, Debug)]
+pub struct ChunkStore {
+    directory: PathBuf,
+}
+
+impl ChunkStore {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        ChunkStore {
+            directory: directory.into(),
+        }
+    }
+
+    pub fn save(
+        &self,
+        column: i64,
+        cells: &[Option<VoxelStruct>],
+        registry: &ElementRegistry,
+    ) -> Result<(), ChunkStoreError> {
+        let file = ColumnFile {
+            elements: registry
+                .iter()
+                .map(|(_, definition)| definition.name.clone())
+                .collect(),
+            cells: Cow::Borrowed(cells),
+        };
+        fs::create_dir_all(&self.directory)?;
+        fs::write(self.path(column), ron::to_string(&file)?)?;
+        Ok(())
+    }
+
+    /// Cells of the column saved last, with the elements of `registry`, `None` if
+    /// it was never saved.
+    pub fn load(
+        &self,
+        column: i64,
+        registry: &ElementRegistry,
+    ) -> Result<Option<Vec<Option<VoxelStruct>>>, ChunkStoreError> {
+        let source = match fs::read_to_string(self.path(column)) {
+            Ok(source) => source,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let ColumnFile {
+            elements: names,
+            cells,
+        } = ron::from_str(&source)?;
+        // Element ids of the file mapped to the ones of the registry
+        let elements = names
+            .iter()
+            .map(|name| registry.find(name))
+            .collect::<Vec<_>>();
+        let mut cells = cells.into_owned();
+        for data in cells.iter_mut().flatten() {
+            let id = data.element.0 as usize;
+            data.element = elements.get(id).copied().flatten().ok_or_else(|| {
+                ChunkStoreError::UnknownElement(names.get(id).cloned().unwrap_or_default())
+            })?;
+        }
+        Ok(Some(cells))
+    }
+
+    /// Columns saved in the store, from left to right.
+    pub fn columns(&self) -> Result<Vec<i64>, ChunkStoreError> {
+        let entries = match fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut columns = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name();
+            let column = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("column_"))
+                .and_then(|name| name.strip_suffix(".ron"))
+                .and_then(|column| column.parse::<i64>().ok());
+            columns.extend(column);
+        }
+        columns.sort_unstable();
+        Ok(columns)
+    }
+
+    /// Deletes every saved column.
+    pub fn clear(&self) -> Result<(), ChunkStoreError> {
+        for column in self.columns()? {
+            fs::remove_file(self.path(column))?;
+        }
+        Ok(())
+    }
+
+    fn path(&self, column: i64) -> PathBuf {
+        self.directory.join(format!("column_{}.ron", column))
+    }
+}
+
+/// World without horizontal limits. The [`GameMap`] only holds the chunk columns
+/// around the player: when the player moves away from its center, the map scrolls,
+/// the columns going out are saved to the store and the ones coming in are loaded
+/// back, or generated the first time.
+#[cfg_attr(feature = "bevy", derive(bevy_ecs::system::Resource))]
+pub struct StreamedWorld {
+    /// World column of the leftmost chunk column of the map.
+    pub first_column: i64,
+    store: ChunkStore,
+    generator: Box<dyn ColumnGenerator>,
+}
+
+impl StreamedWorld {
+    /// Streams the chunk columns of maps `map_width` voxels wide, which must be a
+    /// multiple of [`CHUNK_SIZE`] for the columns to have the same size.
+    pub fn new(
+        store: ChunkStore,
+        generator: impl ColumnGenerator + 'static,
+        map_width: usize,
+    ) -> Result<Self, ChunkStoreError> {
+        if !map_width.is_multiple_of(CHUNK_SIZE) {
+            return Err(ChunkStoreError::UnalignedWidth(map_width));
+        }
+        Ok(StreamedWorld {
+            first_column: 0,
+            store,
+            generator: Box::new(generator),
+        })
+    }
+
+    /// Fills every chunk column of the map. The map is left as it was when a column
+    /// can't be loaded.
+    pub fn load_all(&self, map: &mut GameMap) -> Result<(), ChunkStoreError> {
+        let columns = (0..map.chunks_width())
+            .map(|chunk_x| self.load_column(map, self.first_column + chunk_x as i64))
+            .collect::<Result<Vec<_>, _>>()?;
+        for (chunk_x, cells) in columns.iter().enumerate() {
+            map.set_column_cells(chunk_x, cells);
+        }
+        Ok(())
+    }
+
+    /// Saves every chunk column of the map.
+    pub fn save_all(&self, map: &GameMap) -> Result<(), ChunkStoreError> {
+        (0..map.chunks_width()).try_for_each(|chunk_x| self.save_column(map, chunk_x))
+    }
+
+    /// Cells of the columns saved in the store, except the ones in the map which are
+    /// more recent there.
+    pub fn stored_columns(&self, map: &GameMap) -> Result<Vec<StoredColumn>, ChunkStoreError> {
+        let in_map = self.first_column..self.first_column + map.chunks_width() as i64;
+        let mut columns = Vec::new();
+        for column in self.store.columns()? {
+            if in_map.contains(&column) {
+                continue;
+            }
+            if let Some(cells) = self.store.load(column, map.registry())? {
+                columns.push((column, cells));
+            }
+        }
+        Ok(columns)
+    }
+
+    /// Replaces the columns saved in the store with `columns`, as returned by
+    /// [`StreamedWorld::stored_columns`] with the elements of `registry`.
+    pub fn restore_columns(
+        &self,
+        columns: &[StoredColumn],
+        registry: &ElementRegistry,
+    ) -> Result<(), ChunkStoreError> {
+        self.store.clear()?;
+        columns
+            .iter()
+            .try_for_each(|(column, cells)| self.store.save(*column, cells, registry))
+    }
+
+    /// Scrolls the map so the map column `x` ends up in its middle chunk column.
+    /// Returns the number of chunk columns the content of the map moved to the
+    /// left, negative when it moved to the right. `x` is negative left of the map.
+    /// The incoming columns are loaded first: when one can't be, the map does not
+    /// scroll and no column is saved.
+    pub fn follow(&mut self, map: &mut GameMap, x: isize) -> Result<isize, ChunkStoreError> {
+        let chunks_width = map.chunks_width() as isize;
+        let shift = x.div_euclid(CHUNK_SIZE as isize) - chunks_width / 2;
+        if shift == 0 {
+            return Ok(0);
+        }
+        let first_column = self.first_column + shift as i64;
+        let loaded = if shift > 0 {
+            (chunks_width - shift).max(0)..chunks_width
+        } else {
+            0..(-shift).min(chunks_width)
+        };
+        let columns = loaded
+            .clone()
+            .map(|chunk_x| self.load_column(map, first_column + chunk_x as i64))
+            .collect::<Result<Vec<_>, _>>()?;
+        let evicted = if shift > 0 {
+            0..shift.min(chunks_width)
+        } else {
+            (chunks_width + shift).max(0)..chunks_width
+        };
+        for chunk_x in evicted {
+            self.save_column(map, chunk_x as usize)?;
+        }
+        map.scroll_chunks(shift);
+        self.first_column = first_column;
+        for (chunk_x, cells) in loaded.zip(columns) {
+            map.set_column_cells(chunk_x as usize, &cells);
+        }
+        Ok(shift)
+    }
+
+    fn save_column(&self, map: &GameMap, chunk_x: usize) -> Result<(), ChunkStoreError> {
+        self.store.save(
+            self.first_column + chunk_x as i64,
+            &map.column_cells(chunk_x),
+            map.registry(),
+        )
+    }
+
+    /// Cells of the world column `column`, from the store or generated.
+    fn load_column(
+        &self,
+        map: &GameMap,
+        column: i64,
+    ) -> Result<Vec<Option<VoxelStruct>>, ChunkStoreError> {
+        Ok(match self.store.load(column, map.registry())? {
+            Some(cells) => cells,
+            None => self.generator.generate(column, map.height, map.registry()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::voxel;
+    use crate::voxels::Voxel;
+    use crate::world_position::WorldPosition;
+
+    /// Removes the directory of a test store when the test ends, even on failure.
+    struct TempDir(PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn store(name: &str) -> (ChunkStore, TempDir) {
+        let directory =
+            std::env::temp_dir().join(format!("sandbase_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        (ChunkStore::new(&directory), TempDir(directory))
+    }
+
+    fn world(
+        name: &str,
+        map: &GameMap,
+        generator: impl ColumnGenerator + 'static,
+    ) -> (StreamedWorld, TempDir) {
+        let (store, directory) = store(name);
+        (
+            StreamedWorld::new(store, generator, map.width).unwrap(),
+            directory,
+        )
+    }
+
+    struct StoneFloor;
+
+    impl ColumnGenerator for StoneFloor {
+        fn generate(
+            &self,
+            _: i64,
+            height: usize,
+            registry: &ElementRegistry,
+        ) -> Vec<Option<VoxelStruct>> {
+            let mut cells = vec![None; CHUNK_SIZE * height];
+            if let Voxel::of { data } = registry.spawn_voxel(registry.find("stone").unwrap()) {
+                cells[..CHUNK_SIZE].fill(Some(data));
+            }
+            cells
+        }
+    }
+
+    #[test]
+    fn columns_going_out_of_the_map_come_back() {
+        let mut map = GameMap::new(4 * CHUNK_SIZE, 8);
+        let (mut world, _directory) = world("come_back", &map, EmptyColumns);
+        world.load_all(&mut map).unwrap();
+        map.set_cell(&WorldPosition::new(3, 0), &voxel("sand"));
+
+        assert_eq!(world.follow(&mut map, 10 * CHUNK_SIZE as isize).unwrap(), 8);
+        assert_eq!(world.first_column, 8);
+        assert_eq!(map.iter().count(), 0);
+        while world.first_column > 0 {
+            assert_eq!(world.follow(&mut map, 0).unwrap(), -2);
+        }
+
+        assert_eq!(map.get_cell(&WorldPosition::new(3, 0)), Some(voxel("sand")));
+    }
+
+    #[test]
+    fn maps_scroll_back_to_the_left() {
+        let mut map = GameMap::new(3 * CHUNK_SIZE, 8);
+        let (mut world, _directory) = world("back_left", &map, EmptyColumns);
+        world.load_all(&mut map).unwrap();
+        let sand = WorldPosition::new(CHUNK_SIZE, 0);
+        map.set_cell(&sand, &voxel("sand"));
+        world.follow(&mut map, 2 * CHUNK_SIZE as isize).unwrap();
+        world.follow(&mut map, 2 * CHUNK_SIZE as isize).unwrap();
+        assert_eq!(world.first_column, 2);
+
+        // Left of the map, the camera column is negative
+        assert_eq!(world.follow(&mut map, -1).unwrap(), -2);
+        assert_eq!(world.first_column, 0);
+        assert_eq!(map.get_cell(&sand), Some(voxel("sand")));
+        assert_eq!(world.follow(&mut map, -1).unwrap(), -2);
+        assert_eq!(world.first_column, -2);
+        assert_eq!(map.iter().count(), 0);
+    }
+
+    #[test]
+    fn stored_columns_can_be_put_back() {
+        let mut map = GameMap::new(2 * CHUNK_SIZE, 8);
+        let (mut world, _directory) = world("put_back", &map, EmptyColumns);
+        world.load_all(&mut map).unwrap();
+        map.set_cell(&WorldPosition::new(3, 0), &voxel("sand"));
+        world.follow(&mut map, 4 * CHUNK_SIZE as isize).unwrap();
+        world.save_all(&map).unwrap();
+
+        let columns = world.stored_columns(&map).unwrap();
+        assert_eq!(
+            columns
+                .iter()
+                .map(|(column, _)| *column)
+                .collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        while world.first_column > 0 {
+            world.follow(&mut map, 0).unwrap();
+        }
+        map.delete_cell(&WorldPosition::new(3, 0));
+        map.set_cell(&WorldPosition::new(4, 0), &voxel("stone"));
+        world.follow(&mut map, 4 * CHUNK_SIZE as isize).unwrap();
+        world.restore_columns(&columns, map.registry()).unwrap();
+        while world.first_column > 0 {
+            world.follow(&mut map, 0).unwrap();
+        }
+
+        assert_eq!(map.get_cell(&WorldPosition::new(3, 0)), Some(voxel("sand")));
+        assert_eq!(map.iter().count(), 1);
+    }
+
+    #[test]
+    fn columns_are_loaded_by_element_name() {
+        let (store, _directory) = store("by_name");
+        let registry = ElementRegistry::default();
+        let mut cells = vec![None; CHUNK_SIZE];
+        if let Voxel::of { data } = voxel("water") {
+            cells[2] = Some(data);
+        }
+        store.save(0, &cells, &registry).unwrap();
+        let mut elements = registry
+            .iter()
+            .map(|(_, definition)| definition.clone())
+            .collect::<Vec<_>>();
+        elements.reverse();
+        let reversed = ElementRegistry::new(elements.clone()).unwrap();
+
+        let loaded = store.load(0, &reversed).unwrap().unwrap();
+
+        let element = loaded[2].as_ref().unwrap().element;
+        assert_eq!(reversed.get(element).name, "water");
+        elements.retain(|definition| definition.name == "sand");
+        let sand_only = ElementRegistry::new(elements).unwrap();
+        assert!(matches!(
+            store.load(0, &sand_only),
+            Err(ChunkStoreError::UnknownElement(name)) if name == "water"
+        ));
+    }
+
+    #[test]
+    fn new_columns_are_generated() {
+        let mut map = GameMap::new(2 * CHUNK_SIZE, 8);
+        let (mut world, _directory) = world("generated", &map, StoneFloor);
+        world.load_all(&mut map).unwrap();
+        map.delete_cell(&WorldPosition::new(0, 0));
+
+        world.follow(&mut map, 2 * CHUNK_SIZE as isize).unwrap();
+
+        assert_eq!(
+            map.get_cell(&WorldPosition::new(0, 0)),
+            Some(voxel("stone"))
+        );
+        assert_eq!(map.iter().count(), 2 * CHUNK_SIZE);
+    }
+
+    #[test]
+    fn unreadable_columns_are_not_overwritten() {
+        let mut map = GameMap::new(2 * CHUNK_SIZE, 8);
+        let (mut world, directory) = world("unreadable", &map, StoneFloor);
+        world.load_all(&mut map).unwrap();
+        map.set_cell(&WorldPosition::new(3, 4), &voxel("sand"));
+        fs::create_dir_all(&directory.0).unwrap();
+        let column = directory.0.join("column_2.ron");
+        fs::write(&column, "not a column").unwrap();
+
+        assert!(matches!(
+            world.follow(&mut map, 2 * CHUNK_SIZE as isize),
+            Err(ChunkStoreError::Parse(_))
+        ));
+        assert_eq!(world.first_column, 0);
+        assert_eq!(map.get_cell(&WorldPosition::new(3, 4)), Some(voxel("sand")));
+        assert_eq!(world.store.columns().unwrap(), vec![2]);
+        world.save_all(&map).unwrap();
+        assert_eq!(fs::read_to_string(&column).unwrap(), "not a column");
+    }
+
+    #[test]
+    fn maps_must_hold_whole_chunk_columns() {
+        let (store, _directory) = store("unaligned");
+
+        assert!(matches!(
+            StreamedWorld::new(store, EmptyColumns, CHUNK_SIZE + 1),
+            Err(ChunkStoreError::UnalignedWidth(width)) if width == CHUNK_SIZE + 1
+        ));
+    }
+
+    #[test]
+    fn centered_maps_do_not_scroll() {
+        let mut map = GameMap::new(3 * CHUNK_SIZE, 8);
+        let (mut world, _directory) = world("centered", &map, EmptyColumns);
+        map.take_changes();
+
+        assert_eq!(world.follow(&mut map, CHUNK_SIZE as isize + 5).unwrap(), 0);
+        assert!(map.take_changes().is_empty());
+    }
+}
@@ -0,0 +1,256 @@
+use serde::{Deserialize, Serialize};
+
+use crate::chunk::CHUNK_SIZE;
+use crate::elements::ElementRegistry;
+use crate::rng::Rng;
+use crate::streaming::ColumnGenerator;
+use crate::voxels::{Voxel, VoxelStruct};
+
+/// Typical distance between two dunes, in cells.
+const DUNE_WIDTH: f32 = 24.;
+
+/// Parameters of the generated terrain. Levels are fractions of the map height,
+/// other lengths are in cells.
+#[cfg_attr(feature = "bevy", derive(bevy_ecs::system::Resource))]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TerrainConfig {
+    /// The same seed always generates the same terrain.
+    pub seed: u64,
+    /// Average level of the ground.
+    pub ground_level: f32,
+    /// How far hills and valleys go above and below the ground level.
+    pub hill_height: f32,
+    /// Typical distance between two hilltops.
+    pub hill_width: f32,
+    /// Thickness of the earth layer covering the stone.
+    pub earth_depth: usize,
+    /// Height of the tallest sand dunes.
+    pub dune_height: f32,
+    /// Typical width of the deserts, where dunes are found.
+    pub desert_width: f32,
+    /// Typical size of the caves dug in the stone.
+    pub cave_size: f32,
+    /// Share of the stone dug out by caves, between 0 and 1.
+    pub cave_density: f32,
+    /// Valleys below this level are filled with water.
+    pub water_level: f32,
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        TerrainConfig {
+            seed: 0,
+            ground_level: 0.4,
+            hill_height: 0.15,
+            hill_width: 80.,
+            earth_depth: 6,
+            dune_height: 8.,
+            desert_width: 150.,
+            cave_size: 10.,
+            cave_density: 0.25,
+            water_level: 0.35,
+        }
+    }
+}
+
+/// Seeded terrain made of a stone bed dug with caves, covered with earth, sand
+/// dunes in the deserts and water pools in the valleys.
+#[derive(Clone, Debug, Default)]
+pub struct TerrainGenerator {
+    pub config: TerrainConfig,
+}
+
+impl TerrainGenerator {
+    pub fn new(config: TerrainConfig) -> Self {
+        TerrainGenerator { config }
+    }
+
+    /// Number of ground cells of the world column `x`.
+    fn ground_height(&self, x: i64, height: usize) -> usize {
+        let hills = fractal_noise(self.config.seed, x as f32 / self.config.hill_width) * 2. - 1.;
+        let level = self.config.ground_level + hills * self.config.hill_height;
+        (level * height as f32).max(1.) as usize
+    }
+
+    /// Number of sand cells on top of the ground of the world column `x`.
+    fn dune_height(&self, x: i64) -> usize {
+        let seed = self.config.seed.wrapping_add(1);
+        let desert = noise(seed, x as f32 / self.config.desert_width);
+        let desert = ((desert - 0.5) * 4.).clamp(0., 1.);
+        let dunes = fractal_noise(seed.wrapping_add(1), x as f32 / DUNE_WIDTH);
+        (desert * dunes * self.config.dune_height) as usize
+    }
+
+    fn is_cave(&self, x: i64, y: usize) -> bool {
+        let seed = self.config.seed.wrapping_add(3);
+        let size = self.config.cave_size;
+        noise_2d(seed, x as f32 / size, y as f32 / size) < self.config.cave_density
+    }
+}
+
+impl ColumnGenerator for TerrainGenerator {
+    fn generate(
+        &self,
+        column: i64,
+        height: usize,
+        registry: &ElementRegistry,
+    ) -> Vec<Option<VoxelStruct>> {
+        let spawn = |name: &str| {
+            registry
+                .find(name)
+                .and_then(|element| match registry.spawn_voxel(element) {
+                    Voxel::of { data } => Some(data),
+                    Voxel::OOB => None,
+                })
+        };
+        let (stone, earth, sand, water) = (
+            spawn("stone"),
+            spawn("earth"),
+            spawn("sand"),
+            spawn("water"),
+        );
+        let water_level = (self.config.water_level * height as f32) as usize;
+
+        let mut cells = vec![None; CHUNK_SIZE * height];
+        for dx in 0..CHUNK_SIZE {
+            let x = column * CHUNK_SIZE as i64 + dx as i64;
+            let ground = self.ground_height(x, height);
+            let stone_height = ground.saturating_sub(self.config.earth_depth);
+            let sand_height = ground + self.dune_height(x);
+            for y in 0..height {
+                cells[y * CHUNK_SIZE + dx] = if y < stone_height {
+                    // The bottom row stays solid so caves never open on the void
+                    if y > 0 && self.is_cave(x, y) {
+                        None
+                    } else {
+                        stone
+                    }
+                } else if y < ground {
+                    earth
+                } else if y < sand_height {
+                    sand
+                } else if y < water_level {
+                    water
+                } else {
+                    None
+                };
+                if let Some(data) = &mut cells[y * CHUNK_SIZE + dx] {
+                    let seed = self.config.seed.wrapping_add(5);
+                    data.shade = Rng::for_cell(seed, 0, x as usize, y).next_u64() as u8;
+                }
+            }
+        }
+        cells
+    }
+}
+
+/// Random value between 0 and 1 attached to a lattice point.
+fn lattice(seed: u64, x: i64, y: i64) -> f32 {
+    Rng::for_cell(seed, 0, x as usize, y as usize).next_f32()
+}
+
+fn smoothstep(t: f32) -> f32 {
+    t * t * (3. - 2. * t)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// Smooth value noise between 0 and 1, changing over about one unit of `x`.
+fn noise(seed: u64, x: f32) -> f32 {
+    let x0 = x.floor();
+    let t = smoothstep(x - x0);
+    lerp(
+        lattice(seed, x0 as i64, 0),
+        lattice(seed, x0 as i64 + 1, 0),
+        t,
+    )
+}
+
+fn noise_2d(seed: u64, x: f32, y: f32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (smoothstep(x - x0), smoothstep(y - y0));
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let bottom = lerp(lattice(seed, x0, y0), lattice(seed, x0 + 1, y0), tx);
+    let top = lerp(lattice(seed, x0, y0 + 1), lattice(seed, x0 + 1, y0 + 1), tx);
+    lerp(bottom, top, ty)
+}
+
+/// Three octaves of [`noise`], adding smaller details to the large shapes.
+fn fractal_noise(seed: u64, x: f32) -> f32 {
+    let octaves = [(1., 0.6), (2., 0.3), (4., 0.1)];
+    octaves
+        .iter()
+        .enumerate()
+        .map(|(octave, (frequency, amplitude))| {
+            noise(seed.wrapping_add(octave as u64 * 101), x * frequency) * amplitude
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEIGHT: usize = 64;
+
+    fn element(registry: &ElementRegistry, cell: &Option<VoxelStruct>) -> Option<String> {
+        cell.map(|data| registry.get(data.element).name.clone())
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_terrain() {
+        let registry = ElementRegistry::default();
+        let generate = |seed| {
+            let generator = TerrainGenerator::new(TerrainConfig {
+                seed,
+                ..TerrainConfig::default()
+            });
+            (-3..3)
+                .flat_map(|column| generator.generate(column, HEIGHT, &registry))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(generate(7), generate(7));
+        assert_ne!(generate(7), generate(8));
+    }
+
+    #[test]
+    fn terrain_is_layered_from_stone_to_air() {
+        let registry = ElementRegistry::default();
+        let generator = TerrainGenerator::new(TerrainConfig {
+            cave_density: 0.,
+            water_level: 0.,
+            ..TerrainConfig::default()
+        });
+
+        let cells = generator.generate(0, HEIGHT, &registry);
+
+        for dx in 0..CHUNK_SIZE {
+            let column = (0..HEIGHT)
+                .map(|y| element(&registry, &cells[y * CHUNK_SIZE + dx]))
+                .collect::<Vec<_>>();
+            assert_eq!(column[0].as_deref(), Some("stone"));
+            assert_eq!(column[HEIGHT - 1], None);
+            let first_air = column.iter().position(Option::is_none).unwrap();
+            assert!(column[first_air..].iter().all(Option::is_none));
+        }
+    }
+
+    #[test]
+    fn valleys_below_the_water_level_are_flooded() {
+        let registry = ElementRegistry::default();
+        let generator = TerrainGenerator::new(TerrainConfig {
+            water_level: 0.9,
+            ..TerrainConfig::default()
+        });
+
+        let cells = generator.generate(0, HEIGHT, &registry);
+
+        assert_eq!(
+            element(&registry, &cells[(HEIGHT * 9 / 10 - 1) * CHUNK_SIZE]).as_deref(),
+            Some("water")
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use crate::chunk::CHUNK_SIZE;
+use crate::elements::{ElementRegistry, Kind};
+use crate::image::to_rgb8;
+use crate::map::GameMap;
+use crate::voxels::{Voxel, VoxelStruct};
+use crate::world_position::WorldPosition;
+
+/// Color of burning voxels, whatever their element.
+pub const FIRE_COLOR: [u8; 3] = [255, 115, 13];
+
+/// Brightness of solid voxels touching a liquid, like wet sand.
+const WET_SHADE: f32 = 0.75;
+
+/// Bytes of a pixel, RGBA with 8 bits per channel.
+const PIXEL_SIZE: usize = 4;
+
+const NEIGHBOURS: [(isize, isize); 4] = [(0, -1), (-1, 0), (1, 0), (0, 1)];
+
+/// Color of a voxel on screen, in the shade it was given when it spawned.
+pub fn voxel_color(registry: &ElementRegistry, data: &VoxelStruct) -> [u8; 4] {
+    let [r, g, b] = if data.burning {
+        FIRE_COLOR
+    } else {
+        to_rgb8(registry.shade_color(data.element, data.shade))
+    };
+    [r, g, b, u8::MAX]
+}
+
+/// Color of the cell at `world_position` on screen: the color of its voxel, darker
+/// when wet, and transparent when empty.
+pub fn cell_color(map: &GameMap, world_position: WorldPosition) -> [u8; 4] {
+    match map.get_cell(&world_position) {
+        Some(Voxel::of { data }) if is_wet(map, world_position, &data) => {
+            let [r, g, b, a] = voxel_color(map.registry(), &data);
+            let [r, g, b] = wet_color([r, g, b]);
+            [r, g, b, a]
+        }
+        Some(Voxel::of { data }) => voxel_color(map.registry(), &data),
+        _ => [0; PIXEL_SIZE],
+    }
+}
+
+/// `color` darkened like a wet solid voxel.
+pub(crate) fn wet_color(color: [u8; 3]) -> [u8; 3] {
+    color.map(|channel| (channel as f32 * WET_SHADE) as u8)
+}
+
+/// Block of pixels to copy into the texture. Rows go from the top down, like the
+/// texture rows.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextureRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// RGBA pixels of the map, one per cell, the top row first. Only the cells that
+/// changed are repainted, and the chunks holding them are handed out as regions
+/// so that the texture on the GPU is only partly rewritten.
+#[derive(Clone, Debug)]
+pub struct MapTexture {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<u8>,
+    /// Chunks repainted since the last call to [`MapTexture::take_regions`].
+    dirty: Vec<bool>,
+    chunks_width: usize,
+}
+
+impl MapTexture {
+    /// Paints every cell of the map.
+    pub fn from_map(map: &GameMap) -> Self {
+        let chunks_width = map.width.div_ceil(CHUNK_SIZE);
+        let mut texture = MapTexture {
+            width: map.width,
+            height: map.height,
+            pixels: vec![0; map.width * map.height * PIXEL_SIZE],
+            dirty: vec![false; chunks_width * map.height.div_ceil(CHUNK_SIZE)],
+            chunks_width,
+        };
+        for y in 0..map.height {
+            for x in 0..map.width {
+                texture.paint_cell(map, WorldPosition::new(x, y));
+            }
+        }
+        texture.dirty.fill(false);
+        texture
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Repaints the cells at `changes`, as returned by [`GameMap::take_changes`],
+    /// and their neighbours whose shading depends on them.
+    pub fn paint(&mut self, map: &GameMap, changes: &[WorldPosition]) {
+        for world_position in changes {
+            self.paint_cell(map, *world_position);
+            for (dx, dy) in NEIGHBOURS {
+                if let Some(neighbour) = world_position.offset(dx, dy) {
+                    self.paint_cell(map, neighbour);
+                }
+            }
+        }
+    }
+
+    /// Pixels of the chunks repainted since the previous call.
+    pub fn take_regions(&mut self) -> Vec<TextureRegion> {
+        let mut regions = Vec::new();
+        for chunk in 0..self.dirty.len() {
+            if !std::mem::take(&mut self.dirty[chunk]) {
+                continue;
+            }
+            let x = chunk % self.chunks_width * CHUNK_SIZE;
+            let width = CHUNK_SIZE.min(self.width - x);
+            let bottom = chunk / self.chunks_width * CHUNK_SIZE;
+            let height = CHUNK_SIZE.min(self.height - bottom);
+            let top_row = self.height - bottom - height;
+            let mut pixels = Vec::with_capacity(width * height * PIXEL_SIZE);
+            for row in top_row..top_row + height {
+                let start = (row * self.width + x) * PIXEL_SIZE;
+                pixels.extend_from_slice(&self.pixels[start..start + width * PIXEL_SIZE]);
+            }
+            regions.push(TextureRegion {
+                x: x as u32,
+                y: top_row as u32,
+                width: width as u32,
+                height: height as u32,
+                pixels,
+            });
+        }
+        regions
+    }
+
+    fn paint_cell(&mut self, map: &GameMap, world_position: WorldPosition) {
+        if world_position.x >= self.width || world_position.y >= self.height {
+            return;
+        }
+        let color = cell_color(map, world_position);
+        let row = self.height - 1 - world_position.y;
+        let start = (row * self.width + world_position.x) * PIXEL_SIZE;
+        let pixel = &mut self.pixels[start..start + PIXEL_SIZE];
+        if pixel == color {
+            return;
+        }
+        pixel.copy_from_slice(&color);
+        let chunk =
+            world_position.y / CHUNK_SIZE * self.chunks_width + world_position.x / CHUNK_SIZE;
+        self.dirty[chunk] = true;
+    }
+}
+
+/// Whether a solid voxel touches a liquid.
+fn is_wet(map: &GameMap, world_position: WorldPosition, data: &VoxelStruct) -> bool {
+    let registry = map.registry();
+    registry.get(data.element).kind == Kind::Solid
+        && NEIGHBOURS.iter().any(|(dx, dy)| {
+            let neighbour = world_position.offset(*dx, *dy);
+            matches!(
+                neighbour.and_then(|neighbour| map.get_cell(&neighbour)),
+                Some(Voxel::of { data }) if registry.get(data.element).kind == Kind::Liquid
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::voxel;
+
+    #[test]
+    fn only_repainted_chunks_are_handed_out() {
+        let mut map = GameMap::new(2 * CHUNK_SIZE, CHUNK_SIZE + 8);
+        let mut texture = MapTexture::from_map(&map);
+        assert!(texture.take_regions().is_empty());
+
+        map.set_cell(
+            &WorldPosition::new(CHUNK_SIZE + 1, CHUNK_SIZE),
+            &voxel("sand"),
+        );
+        let changes = map.take_changes();
+        texture.paint(&map, &changes);
+        let regions = texture.take_regions();
+
+        assert_eq!(regions.len(), 1);
+        let region = &regions[0];
+        assert_eq!(
+            (region.x, region.y, region.width, region.height),
+            (CHUNK_SIZE as u32, 0, CHUNK_SIZE as u32, 8)
+        );
+        // The cell is on the bottom row of the region, second column
+        let start = ((region.height as usize - 1) * CHUNK_SIZE + 1) * PIXEL_SIZE;
+        let registry = ElementRegistry::default();
+        let [r, g, b] = to_rgb8(registry.get(registry.find("sand").unwrap()).color);
+        assert_eq!(&region.pixels[start..start + PIXEL_SIZE], &[r, g, b, 255]);
+        assert!(texture.take_regions().is_empty());
+    }
+
+    #[test]
+    fn burning_voxels_are_drawn_as_fire() {
+        let registry = ElementRegistry::default();
+        let mut data = match voxel("wood") {
+            Voxel::of { data } => data,
+            Voxel::OOB => unreachable!(),
+        };
+        data.burning = true;
+
+        assert_eq!(voxel_color(&registry, &data)[..3], FIRE_COLOR);
+    }
+
+    #[test]
+    fn sand_next_to_water_is_darker() {
+        let mut map = GameMap::new(4, 4);
+        map.set_cell(&WorldPosition::new(1, 0), &voxel("sand"));
+        map.set_cell(&WorldPosition::new(3, 0), &voxel("sand"));
+        let mut texture = MapTexture::from_map(&map);
+        let pixel = |texture: &MapTexture, x: usize| {
+            let start = (3 * 4 + x) * PIXEL_SIZE;
+            texture.pixels()[start..start + PIXEL_SIZE].to_vec()
+        };
+        assert_eq!(pixel(&texture, 1), pixel(&texture, 3));
+
+        map.set_cell(&WorldPosition::new(1, 1), &voxel("water"));
+        let changes = map.take_changes();
+        texture.paint(&map, &changes);
+
+        assert!(pixel(&texture, 1)[0] < pixel(&texture, 3)[0]);
+    }
+}
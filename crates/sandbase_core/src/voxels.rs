@@ -0,0 +1,519 @@
+use serde::{Deserialize, Serialize};
+
+use crate::elements::{
+    Element, ElementDefinition, ElementRegistry, Kind, Reaction, IGNITION_TEMPERATURE,
+};
+use crate::map::GameMap;
+use crate::world_position::WorldPosition;
+
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Voxel {
+    OOB,
+    of { data: VoxelStruct },
+}
+
+impl Voxel {
+    /// Computes where the voxel standing at `world_position` wants to go, without
+    /// modifying the map. The candidate cells and how to enter them come from the
+    /// element definition, gases try them in a random order.
+    pub fn update(&self, map: &GameMap, world_position: WorldPosition) -> Option<Move> {
+        match self {
+            Voxel::of { data } => {
+                let registry = map.registry();
+                let definition = registry.get(data.element);
+                if let Some(reaction) = Voxel::react(map, data.element, world_position) {
+                    return Some(reaction);
+                }
+                if data.temperature >= IGNITION_TEMPERATURE {
+                    if let Some(ignition) = Voxel::ignite(map, world_position) {
+                        return Some(ignition);
+                    }
+                }
+                if definition.kind == Kind::Static {
+                    return None;
+                }
+                let mut moves = definition.moves.clone();
+                if definition.kind == Kind::Gas {
+                    map.rng(world_position).shuffle(&mut moves);
+                }
+                let mut leveled = false;
+                moves
+                    .iter()
+                    .find_map(|(dx, dy)| {
+                        if *dy == 0 && *dx != 0 {
+                            // Flowing down to a lower surface comes before spreading
+                            // on this one
+                            if definition.kind == Kind::Liquid && !leveled {
+                                leveled = true;
+                                let level =
+                                    Voxel::level(definition, map, data.element, world_position);
+                                if level.is_some() {
+                                    return level;
+                                }
+                            }
+                            return Voxel::disperse(
+                                registry,
+                                definition,
+                                map,
+                                world_position,
+                                *dx,
+                                definition.dispersion,
+                            );
+                        }
+                        let (maybe_voxel, new_world_position) =
+                            map.get_relative_voxel(world_position, *dx, *dy);
+                        Voxel::density_behaviour(
+                            registry,
+                            definition,
+                            &maybe_voxel,
+                            new_world_position,
+                            *dy,
+                        )
+                    })
+                    .or_else(|| match definition.kind {
+                        Kind::Liquid if !leveled => {
+                            Voxel::level(definition, map, data.element, world_position)
+                        }
+                        _ => None,
+                    })
+            }
+            // no-op for Out Of Bounds voxels
+            Voxel::OOB => None,
+        }
+    }
+
+    /// Looks for a neighbour the element reacts with, each reaction happening with its
+    /// own probability.
+    fn react(map: &GameMap, element: Element, world_position: WorldPosition) -> Option<Move> {
+        let reactions = map.registry().reactions(element);
+        if reactions.is_empty() {
+            return None;
+        }
+        let mut rng = map.rng(world_position);
+        [
+            map.get_bottom_voxel(world_position),
+            map.get_left_voxel(world_position),
+            map.get_right_voxel(world_position),
+            map.get_top_voxel(world_position),
+        ]
+        .iter()
+        .find_map(|(maybe_voxel, other)| match maybe_voxel {
+            Some(Voxel::of { data }) => reactions
+                .iter()
+                .find(|reaction| reaction.with == data.element && rng.chance(reaction.probability))
+                .map(|reaction| Move::React {
+                    other: *other,
+                    reaction: *reaction,
+                }),
+            _ => None,
+        })
+    }
+
+    /// Whether the voxel touches a neighbour it may react with or set fire to in a
+    /// later tick. Such voxels are kept awake even when they don't move.
+    pub fn is_reactive(&self, map: &GameMap, world_position: WorldPosition) -> bool {
+        let data = match self {
+            Voxel::of { data } => data,
+            Voxel::OOB => return false,
+        };
+        let registry = map.registry();
+        let reactions = registry.reactions(data.element);
+        let hot = data.temperature >= IGNITION_TEMPERATURE;
+        if reactions.is_empty() && !hot {
+            return false;
+        }
+        [
+            map.get_bottom_voxel(world_position),
+            map.get_left_voxel(world_position),
+            map.get_right_voxel(world_position),
+            map.get_top_voxel(world_position),
+        ]
+        .iter()
+        .any(|(maybe_voxel, _)| match maybe_voxel {
+            Some(Voxel::of { data: other }) => {
+                reactions
+                    .iter()
+                    .any(|reaction| reaction.with == other.element && reaction.probability > 0.)
+                    || (hot && !other.burning && registry.get(other.element).flammability > 0.)
+            }
+            _ => false,
+        })
+    }
+
+    /// Sets fire to a flammable neighbour that isn't burning yet, with the chance of
+    /// its flammability.
+    fn ignite(map: &GameMap, world_position: WorldPosition) -> Option<Move> {
+        let registry = map.registry();
+        let mut rng = map.rng(world_position);
+        [
+            map.get_top_voxel(world_position),
+            map.get_left_voxel(world_position),
+            map.get_right_voxel(world_position),
+            map.get_bottom_voxel(world_position),
+        ]
+        .iter()
+        .find_map(|(maybe_voxel, other)| match maybe_voxel {
+            Some(Voxel::of { data })
+                if !data.burning && rng.chance(registry.get(data.element).flammability) =>
+            {
+                Some(Move::Ignite(*other))
+            }
+            _ => None,
+        })
+    }
+
+    /// Computes where the voxel bounces after hitting an obstacle at `velocity`:
+    /// falling solids and liquids splash sideways in a random direction, up to half
+    /// as many cells as they were falling per tick.
+    pub fn splash(
+        &self,
+        map: &GameMap,
+        world_position: WorldPosition,
+        velocity: f32,
+    ) -> Option<Move> {
+        match self {
+            Voxel::of { data } => {
+                let registry = map.registry();
+                let definition = registry.get(data.element);
+                if matches!(definition.kind, Kind::Static | Kind::Gas) {
+                    return None;
+                }
+                let dx = if map.rng(world_position).chance(0.5) {
+                    -1
+                } else {
+                    1
+                };
+                let range = (velocity / 2.) as usize;
+                Voxel::disperse(registry, definition, map, world_position, dx, range)
+            }
+            Voxel::OOB => None,
+        }
+    }
+
+    /// Flows up to `range` cells in the direction of `dx`, stopping at the first
+    /// obstacle or above the first hole so the voxel falls through it.
+    fn disperse(
+        registry: &ElementRegistry,
+        definition: &ElementDefinition,
+        map: &GameMap,
+        world_position: WorldPosition,
+        dx: isize,
+        range: usize,
+    ) -> Option<Move> {
+        let cells = if dx < 0 {
+            map.get_left_voxels(world_position, range)
+        } else {
+            map.get_right_voxels(world_position, range)
+        };
+        let mut target = None;
+        for (distance, (maybe_voxel, new_world_position)) in cells.into_iter().enumerate() {
+            match maybe_voxel {
+                None => {
+                    target = Some(Move::Displace(new_world_position));
+                    if map.get_bottom_voxel(new_world_position).0.is_none() {
+                        break;
+                    }
+                }
+                _ if distance == 0 => {
+                    return Voxel::density_behaviour(
+                        registry,
+                        definition,
+                        &maybe_voxel,
+                        new_world_position,
+                        0,
+                    )
+                }
+                _ => break,
+            }
+        }
+        target
+    }
+
+    /// Pressure-like leveling: the top voxel of a liquid body flows through the body
+    /// to a free cell lower than itself, so communicating vessels end up at the same
+    /// level. Liquids try it before their sideways moves. The body is scanned in straight lines: down the column of the voxel,
+    /// along a row, then up to the surface, each at most `dispersion` cells long.
+    fn level(
+        definition: &ElementDefinition,
+        map: &GameMap,
+        element: Element,
+        world_position: WorldPosition,
+    ) -> Option<Move> {
+        let cell = |dx: isize, dy: isize| map.get_relative_voxel(world_position, dx, dy);
+        let is_same_element = |dx: isize, dy: isize| match cell(dx, dy).0 {
+            Some(Voxel::of { data }) => data.element == element,
+            _ => false,
+        };
+        if is_same_element(0, 1) {
+            return None;
+        }
+
+        let reach = definition.dispersion as isize;
+        for depth in (1..=reach).take_while(|depth| is_same_element(0, -depth)) {
+            for direction in [-1, 1] {
+                for dx in (1..=reach).map(|distance| direction * distance) {
+                    if !is_same_element(dx, -depth) {
+                        match cell(dx, -depth) {
+                            (None, free_position) => return Some(Move::Displace(free_position)),
+                            _ => break,
+                        }
+                    }
+                    // Up to the surface, lower than the voxel
+                    for dy in (1 - depth)..0 {
+                        match cell(dx, dy) {
+                            (None, free_position) => return Some(Move::Displace(free_position)),
+                            _ if is_same_element(dx, dy) => (),
+                            _ => break,
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// A voxel enters empty cells, sinks through lighter liquids and gases and rises
+    /// through heavier ones. Solids and static voxels are never displaced.
+    fn density_behaviour(
+        registry: &ElementRegistry,
+        definition: &ElementDefinition,
+        other_voxel: &Option<Voxel>,
+        new_pos: WorldPosition,
+        dy: isize,
+    ) -> Option<Move> {
+        match other_voxel {
+            None => Some(Move::Displace(new_pos)),
+            Some(Voxel::of { data }) => {
+                let other_definition = registry.get(data.element);
+                let sinks = dy <= 0 && other_definition.density < definition.density;
+                let rises = dy > 0 && other_definition.density > definition.density;
+                let is_fluid = matches!(other_definition.kind, Kind::Liquid | Kind::Gas);
+                if is_fluid && (sinks || rises) {
+                    Some(Move::Swap(new_pos))
+                } else {
+                    None
+                }
+            }
+            Some(Voxel::OOB) => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct VoxelStruct {
+    /// Falling speed in cells per tick, growing with gravity up to the element
+    /// `speed`. The voxel travels that many cells per tick, one cell at a time:
+    /// fractions are dropped and it always moves at least once.
+    pub velocity: f32,
+    pub element: Element,
+    /// Ticks left before the voxel disappears, `None` for voxels living forever.
+    pub lifetime: Option<u32>,
+    /// Temperature in °C.
+    pub temperature: f32,
+    /// Burning voxels stay hot, release smoke and are consumed when their lifetime
+    /// runs out.
+    pub burning: bool,
+    /// Index in the element palette, picked when the voxel spawns and kept as it
+    /// moves.
+    #[serde(default)]
+    pub shade: u8,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Move {
+    Displace(WorldPosition),
+    Swap(WorldPosition),
+    /// The voxel reacts with its neighbour at `other`.
+    React {
+        other: WorldPosition,
+        reaction: Reaction,
+    },
+    /// The voxel sets fire to its neighbour.
+    Ignite(WorldPosition),
+}
+
+impl Move {
+    /// Where the moving voxel ends up, `None` if it doesn't move.
+    pub fn destination(&self) -> Option<WorldPosition> {
+        match self {
+            Move::Displace(world_position) | Move::Swap(world_position) => Some(*world_position),
+            Move::React { .. } | Move::Ignite(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::voxel;
+
+    #[test]
+    fn sand_falls_straight_down_when_bottom_is_free() {
+        let mut map = GameMap::new(3, 3);
+        let sand = voxel("sand");
+        map.set_cell(&WorldPosition::new(1, 2), &sand);
+
+        assert_eq!(
+            sand.update(&map, WorldPosition::new(1, 2)),
+            Some(Move::Displace(WorldPosition::new(1, 1)))
+        );
+    }
+
+    #[test]
+    fn sand_slides_on_top_of_another_voxel() {
+        let mut map = GameMap::new(3, 3);
+        let sand = voxel("sand");
+        map.set_cell(&WorldPosition::new(1, 0), &voxel("earth"));
+        map.set_cell(&WorldPosition::new(1, 1), &sand);
+
+        assert_eq!(
+            sand.update(&map, WorldPosition::new(1, 1)),
+            Some(Move::Displace(WorldPosition::new(0, 0)))
+        );
+    }
+
+    #[test]
+    fn sand_wants_to_swap_with_water_below() {
+        let mut map = GameMap::new(3, 3);
+        let sand = voxel("sand");
+        map.set_cell(&WorldPosition::new(1, 0), &voxel("water"));
+        map.set_cell(&WorldPosition::new(1, 1), &sand);
+
+        assert_eq!(
+            sand.update(&map, WorldPosition::new(1, 1)),
+            Some(Move::Swap(WorldPosition::new(1, 0)))
+        );
+    }
+
+    #[test]
+    fn water_sinks_under_oil() {
+        let mut map = GameMap::new(1, 2);
+        let water = voxel("water");
+        map.set_cell(&WorldPosition::new(0, 0), &voxel("oil"));
+        map.set_cell(&WorldPosition::new(0, 1), &water);
+
+        assert_eq!(
+            water.update(&map, WorldPosition::new(0, 1)),
+            Some(Move::Swap(WorldPosition::new(0, 0)))
+        );
+    }
+
+    #[test]
+    fn sand_does_not_push_earth() {
+        let mut map = GameMap::new(1, 2);
+        let sand = voxel("sand");
+        map.set_cell(&WorldPosition::new(0, 0), &voxel("earth"));
+        map.set_cell(&WorldPosition::new(0, 1), &sand);
+
+        assert_eq!(sand.update(&map, WorldPosition::new(0, 1)), None);
+    }
+
+    #[test]
+    fn static_voxels_never_move() {
+        let map = GameMap::new(3, 3);
+        let stone = voxel("stone");
+
+        assert_eq!(stone.update(&map, WorldPosition::new(1, 2)), None);
+    }
+
+    #[test]
+    fn water_rests_on_lighter_wood() {
+        let mut map = GameMap::new(1, 2);
+        let water = voxel("water");
+        map.set_cell(&WorldPosition::new(0, 0), &voxel("wood"));
+        map.set_cell(&WorldPosition::new(0, 1), &water);
+
+        assert_eq!(water.update(&map, WorldPosition::new(0, 1)), None);
+    }
+
+    #[test]
+    fn water_flows_sideways_when_resting_on_the_floor() {
+        let mut map = GameMap::new(3, 1);
+        let water = voxel("water");
+        map.set_cell(&WorldPosition::new(1, 0), &water);
+
+        assert_eq!(
+            water.update(&map, WorldPosition::new(1, 0)),
+            Some(Move::Displace(WorldPosition::new(0, 0)))
+        );
+    }
+
+    #[test]
+    fn water_disperses_several_cells_sideways() {
+        let mut map = GameMap::new(12, 1);
+        let water = voxel("water");
+        map.set_cell(&WorldPosition::new(0, 0), &water);
+
+        let dispersion = map
+            .registry()
+            .get(map.registry().find("water").unwrap())
+            .dispersion;
+        assert_eq!(
+            water.update(&map, WorldPosition::new(0, 0)),
+            Some(Move::Displace(WorldPosition::new(dispersion, 0)))
+        );
+    }
+
+    #[test]
+    fn dispersing_water_stops_above_holes() {
+        let mut map = GameMap::new(12, 2);
+        let water = voxel("water");
+        for x in 0..12 {
+            if x != 3 {
+                map.set_cell(&WorldPosition::new(x, 0), &voxel("earth"));
+            }
+        }
+        map.set_cell(&WorldPosition::new(0, 1), &water);
+
+        assert_eq!(
+            water.update(&map, WorldPosition::new(0, 1)),
+            Some(Move::Displace(WorldPosition::new(3, 1)))
+        );
+    }
+
+    #[test]
+    fn water_flows_through_its_body_to_a_lower_surface() {
+        let mut map = GameMap::new(3, 5);
+        let water = voxel("water");
+        for y in 0..4 {
+            map.set_cell(&WorldPosition::new(0, y), &water);
+        }
+        for y in 1..5 {
+            map.set_cell(&WorldPosition::new(1, y), &voxel("stone"));
+        }
+        map.set_cell(&WorldPosition::new(1, 0), &water);
+        map.set_cell(&WorldPosition::new(2, 0), &water);
+
+        assert_eq!(
+            water.update(&map, WorldPosition::new(0, 3)),
+            Some(Move::Displace(WorldPosition::new(2, 1)))
+        );
+        // Both sides at the same level
+        map.set_cell(&WorldPosition::new(2, 1), &water);
+        map.set_cell(&WorldPosition::new(2, 2), &water);
+        assert_eq!(water.update(&map, WorldPosition::new(0, 3)), None);
+    }
+
+    #[test]
+    fn fast_sand_splashes_sideways_on_impact() {
+        let mut map = GameMap::new(5, 1);
+        let sand = voxel("sand");
+        map.set_cell(&WorldPosition::new(2, 0), &sand);
+
+        assert!(matches!(
+            sand.splash(&map, WorldPosition::new(2, 0), 4.),
+            Some(Move::Displace(WorldPosition { x: 0 | 4, y: 0 }))
+        ));
+        assert_eq!(sand.splash(&map, WorldPosition::new(2, 0), 1.), None);
+    }
+
+    #[test]
+    fn earth_on_the_floor_is_stuck() {
+        let mut map = GameMap::new(3, 3);
+        let earth = voxel("earth");
+        map.set_cell(&WorldPosition::new(1, 0), &earth);
+
+        assert_eq!(earth.update(&map, WorldPosition::new(1, 0)), None);
+    }
+}
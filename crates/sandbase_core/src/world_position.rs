@@ -0,0 +1,51 @@
+use std::ops::{Add, Sub};
+
+/// Position of a cell in the world grid, `y = 0` being the bottom row.
+#[cfg_attr(feature = "bevy", derive(bevy_ecs::component::Component))]
+#[derive(Default, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct WorldPosition {
+    pub x: usize,
+    pub y: usize,
+}
+
+impl WorldPosition {
+    pub fn new(x: usize, y: usize) -> Self {
+        WorldPosition { x, y }
+    }
+
+    /// Returns the position shifted by `(dx, dy)`, or `None` if it would leave the
+    /// positive quadrant.
+    pub fn offset(&self, dx: isize, dy: isize) -> Option<WorldPosition> {
+        let x = self.x as isize + dx;
+        let y = self.y as isize + dy;
+        if x < 0 || y < 0 {
+            return None;
+        }
+        Some(WorldPosition {
+            x: x as usize,
+            y: y as usize,
+        })
+    }
+}
+
+impl Sub for WorldPosition {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self::Output {
+        Self {
+            x: self.x - other.x,
+            y: self.y - other.y,
+        }
+    }
+}
+
+impl Add for WorldPosition {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            x: self.x + other.x,
+            y: self.y + other.y,
+        }
+    }
+}
@@ -2,6 +2,7 @@ use bevy::prelude::*;
 use std::ops::Sub;
 
 use crate::components::positions::snapped_position::SnappedPosition;
+use crate::components::positions::world_position::WorldPosition;
 use crate::resources::window::size::ScreenSize;
 use crate::resources::world::config::WorldConfig;
 
@@ -20,10 +21,29 @@ impl ScreenPosition {
         ScreenPosition { x: v.x, y: v.y }
     }
 
+    /// Position of the camera at `translation` relative to the one showing the
+    /// bottom-left voxel of the map in the middle of the window.
+    pub fn from_camera(
+        translation: Vec3,
+        window_size: &ScreenSize,
+        world_config: &WorldConfig,
+    ) -> Self {
+        let camera_origin = ScreenPosition {
+            x: window_size.width / 2.0 - world_config.px_per_voxel as f32 / 2.0,
+            y: window_size.height / 2.0 - world_config.px_per_voxel as f32 / 2.0,
+        };
+        ScreenPosition::from_vec3(translation) - camera_origin
+    }
+
     pub fn to_snapped(&self, world_config: &WorldConfig) -> SnappedPosition {
         SnappedPosition::from_screen_position(self, world_config)
     }
 
+    pub fn to_world_position(&self, world_config: &WorldConfig) -> WorldPosition {
+        self.to_snapped(world_config)
+            .to_world_position(world_config.px_per_voxel)
+    }
+
     pub fn to_vec3(&self) -> Vec3 {
         Vec3::new(self.x, self.y, 0.0)
     }
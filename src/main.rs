@@ -1,16 +1,20 @@
 use bevy::prelude::*;
 
-use components::voxels::*;
+use sandbase_core::{
+    ChunkStore, ElementRegistry, GameMap, Simulation, SimulationClock, StreamedWorld,
+    TerrainConfig, TerrainGenerator, CHUNK_SIZE,
+};
 
-use crate::components::positions::screen_position::ScreenPosition;
 use crate::plugins::inputs::InputsPluginGroup;
 use crate::resources::voxels::default_mesh::VoxelMesh;
 use crate::resources::window::size::ScreenSize;
 use crate::resources::world::config::WorldConfig;
-use crate::resources::world::map::GameMap;
 use crate::resources::world::player_world_viewpoint::PlayerWorldViewpoint;
+use crate::systems::brush::BrushPlugin;
 use crate::systems::inputs::{game_cursor, keyboard};
-use crate::systems::{camera, startup};
+use crate::systems::rendering::VoxelRenderPlugin;
+use crate::systems::tools::ToolsPlugin;
+use crate::systems::{camera, simulation, startup};
 
 mod components;
 mod plugins;
@@ -18,14 +22,28 @@ mod resources;
 mod systems;
 
 const BACKGROUND: Color = Color::rgb(0., 0., 0.);
-const WATER: Color = Color::rgb(0., 0.749, 1.);
-const SAND: Color = Color::rgb(0.761, 0.698, 0.);
-const EARTH: Color = Color::rgb(0.545, 0.271, 0.075);
+const ELEMENTS_PATH: &str = "assets/elements.ron";
+/// Directory of the chunks unloaded from the map.
+const WORLD_PATH: &str = "saves/world";
+/// File written and read back with the save keys.
+const SAVE_PATH: &str = "saves/world.sbsv";
+/// Level image imported into the map, one pixel per voxel.
+const LEVEL_PATH: &str = "assets/level.png";
+const SCREENSHOT_PATH: &str = "saves/screenshot.png";
+/// Environment variable picking the simulation backend, `cpu` or `gpu`.
+const BACKEND_VAR: &str = "SANDBASE_BACKEND";
 
 fn main() {
-    let voxels_width = 133;
+    // Chunk columns kept in memory around the camera, the world itself is unlimited
+    let voxels_width = 5 * CHUNK_SIZE;
     let voxels_height = 72;
     let world_config = WorldConfig::new(voxels_width, voxels_height, 10);
+    // Terrain generated for the chunks never visited, the seed also drives the simulation
+    let terrain_config = TerrainConfig::default();
+    let element_registry = load_element_registry();
+    let simulation = simulation_backend(&element_registry);
+    let mut map = GameMap::with_registry(voxels_width, voxels_height, element_registry);
+    map.seed = terrain_config.seed;
     App::new()
         .add_plugins(DefaultPlugins.set(WindowPlugin {
             primary_window: Some(Window {
@@ -34,63 +52,102 @@ fn main() {
             }),
             ..default()
         }))
-        .add_plugins(InputsPluginGroup)
+        // Before the plugins, which read the map when they are built
         .insert_resource(world_config)
-        .insert_resource(GameMap::new(voxels_width, voxels_height))
+        .insert_resource(
+            StreamedWorld::new(
+                ChunkStore::new(WORLD_PATH),
+                TerrainGenerator::new(terrain_config.clone()),
+                voxels_width,
+            )
+            .unwrap_or_else(|e| panic!("Can't stream the world: {}", e)),
+        )
+        .insert_resource(terrain_config)
+        .insert_resource(map)
+        .insert_resource(simulation)
+        .add_plugins(InputsPluginGroup)
+        .add_plugin(VoxelRenderPlugin)
+        .add_plugin(BrushPlugin)
+        .add_plugin(ToolsPlugin)
+        .init_resource::<SimulationClock>()
         .init_resource::<PlayerWorldViewpoint>()
-        .init_resource::<VoxelManager>()
         .init_resource::<VoxelMesh>()
         .init_resource::<ScreenSize>()
         .add_system(camera::handle_window_resize)
         .add_system(camera::handle_keyboard)
+        .add_system(
+            simulation::stream_world
+                .after(camera::handle_keyboard)
+                .before(simulation::update_voxel_world),
+        )
         .add_startup_system(startup::setup)
+        .add_startup_system(simulation::load_world)
         .add_startup_system(startup::setup_ui)
         .add_startup_system(game_cursor::setup_voxel_scene)
         .add_system(game_cursor::handle_cursor_moved)
         .add_system(game_cursor::handle_camera_move)
-        // TODO: make it one function
-        .add_system(game_cursor::handle_button)
         .add_system(keyboard::handle_input)
-        .add_system(update_voxel_world)
+        .add_system(simulation::handle_clock_keys.before(simulation::update_voxel_world))
+        .add_system(
+            simulation::handle_save_keys
+                .after(simulation::stream_world)
+                .before(simulation::update_voxel_world),
+        )
+        .add_system(simulation::handle_image_keys.before(simulation::update_voxel_world))
+        .add_system(simulation::update_voxel_world)
+        .add_system(simulation::save_world.in_base_set(CoreSet::Last))
         .run();
 }
 
-fn update_voxel_world(
-    world_config: Res<WorldConfig>,
-    screen_size: Res<ScreenSize>,
-    mut map: ResMut<GameMap>,
-    mut query: Query<(&mut Transform, &mut Voxel)>,
-) {
-    for (mut transform, voxel) in query.iter_mut() {
-        let screen_position = ScreenPosition::from_vec2(transform.translation.truncate());
-        let snapped_position = screen_position.to_snapped(&world_config);
-        let world_position = snapped_position.to_world_position(world_config.px_per_voxel);
+/// Reads the element definitions from the assets, falling back to the built-in
+/// ones when the file is missing.
+fn load_element_registry() -> ElementRegistry {
+    match std::fs::read_to_string(ELEMENTS_PATH) {
+        Ok(source) => ElementRegistry::from_ron(&source)
+            .unwrap_or_else(|e| panic!("Invalid element definitions in {}: {}", ELEMENTS_PATH, e)),
+        Err(e) => {
+            println!(
+                "Can't read {}: {}, using default elements",
+                ELEMENTS_PATH, e
+            );
+            ElementRegistry::default()
+        }
+    }
+}
+
+/// Backend chosen with [`BACKEND_VAR`], the CPU one unless the GPU one was asked
+/// for and can run the elements.
+fn simulation_backend(registry: &ElementRegistry) -> Simulation {
+    match std::env::var(BACKEND_VAR).as_deref() {
+        Ok("gpu") => gpu_backend(registry),
+        _ => Simulation::default(),
+    }
+}
 
-        match voxel.update(&*world_config, &mut map, world_position) {
-            Some(Move::Displace(new_world_position)) => {
-                map.delete_cell(&world_position);
-                map.set_cell(&new_world_position, &*voxel);
-                transform.translation = new_world_position
-                    .to_snapped(world_config.px_per_voxel)
-                    .to_screen_position()
-                    .to_vec3();
-            }
-            /*            Some(Move::Swap(new_index)) => {
-                let tmp_voxel = world.voxels[new_index];
-                world.voxels[new_index] = old_voxel;
-                world.voxels[index] = tmp_voxel;
-                let old_pos = WorldPosition::from_index(new_index, world_config.voxels_width).as_snapped(world_config.voxel_size).as_screen_position();
-                let new_pos = WorldPosition::from_index(new_index, world_config.voxels_width).as_snapped(world_config.voxel_size).as_screen_position();
-                transform.translation = Vec3::new(new_pos.x, new_pos.y, 0.0);
-                let Ok((mut transform_old, mut _voxel)) = query.get_mut(tmp_voxel.unwrap().get_id().unwrap());
-                transform_old.translation = Vec3::new(old_pos.x, old_pos.y, 0.0);
-            }*/
-            // No-op, voxel is currently stuck
-            _ => (),
+#[cfg(feature = "gpu")]
+fn gpu_backend(registry: &ElementRegistry) -> Simulation {
+    if let Err(e) = sandbase_core::GpuBackend::check_rules(registry) {
+        println!("Can't simulate on the GPU: {}, using the CPU", e);
+        return Simulation::default();
+    }
+    match sandbase_core::GpuBackend::new() {
+        Ok(backend) => {
+            println!("Simulating on {}", backend.adapter_name());
+            Simulation::new(backend)
+        }
+        Err(e) => {
+            println!("Can't simulate on the GPU: {}, using the CPU", e);
+            Simulation::default()
         }
     }
 }
 
+#[cfg(not(feature = "gpu"))]
+fn gpu_backend(_: &ElementRegistry) -> Simulation {
+    println!("Built without the gpu feature, simulating on the CPU");
+    Simulation::default()
+}
+
 enum AppState {
     InGame,
 }
@@ -0,0 +1,207 @@
+use bevy::input::mouse::MouseWheel;
+use bevy::prelude::*;
+
+use sandbase_core::rng::Rng;
+use sandbase_core::{outline, Brush, BrushShape, Element, GameMap, MAX_RADIUS};
+
+use crate::components::positions::snapped_position::SnappedPosition;
+use crate::components::positions::world_position::WorldPosition;
+use crate::resources::world::config::WorldConfig;
+use crate::systems::simulation;
+use crate::systems::tools::Tool;
+
+/// Change of the spray density per wheel step.
+const DENSITY_STEP: f32 = 0.1;
+const MIN_DENSITY: f32 = 0.1;
+const PREVIEW_COLOR: Color = Color::rgba(1., 1., 1., 0.35);
+
+/// Paints the selected element with the [`Brush`], or erases with it, while the
+/// left mouse button is held, and outlines the brush under the cursor.
+///
+/// The wheel changes the radius, the spray density with Shift and the scatter
+/// with Ctrl. B switches between the circle, square and line shapes: lines are
+/// painted from where the button was pressed to where it is released.
+pub struct BrushPlugin;
+
+impl Plugin for BrushPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Brush>()
+            .init_resource::<SelectedElement>()
+            .init_resource::<BrushStroke>()
+            .init_resource::<SnappedPosition>()
+            .add_system(handle_brush_keys)
+            .add_system(handle_brush_wheel)
+            .add_system(
+                paint_with_brush
+                    .after(handle_brush_keys)
+                    .before(simulation::update_voxel_world),
+            )
+            .add_system(update_brush_preview.after(paint_with_brush));
+    }
+}
+
+/// Element painted by the brush, picked with the element buttons of the
+/// [`ToolsPlugin`](crate::systems::tools::ToolsPlugin).
+#[derive(Resource, Copy, Clone, Debug, Deref, DerefMut)]
+pub struct SelectedElement(pub Element);
+
+impl FromWorld for SelectedElement {
+    fn from_world(world: &mut World) -> Self {
+        let sand = world
+            .get_resource::<GameMap>()
+            .and_then(|map| map.registry().find("sand"));
+        SelectedElement(sand.unwrap_or(Element(0)))
+    }
+}
+
+/// State of the stroke being painted.
+#[derive(Resource)]
+pub struct BrushStroke {
+    /// Where the button was pressed, `None` when it isn't held.
+    start: Option<WorldPosition>,
+    /// Cursor position when the brush was last stamped.
+    last: WorldPosition,
+    rng: Rng,
+}
+
+impl Default for BrushStroke {
+    fn default() -> Self {
+        BrushStroke {
+            start: None,
+            last: WorldPosition::default(),
+            rng: Rng::new(0),
+        }
+    }
+}
+
+/// Marks the sprites outlining the brush.
+#[derive(Component)]
+pub struct BrushPreview;
+
+pub fn handle_brush_keys(keys: Res<Input<KeyCode>>, mut brush: ResMut<Brush>) {
+    if keys.just_pressed(KeyCode::B) {
+        brush.shape = brush.shape.next();
+    }
+}
+
+pub fn handle_brush_wheel(
+    mut wheel_events: EventReader<MouseWheel>,
+    keys: Res<Input<KeyCode>>,
+    mut brush: ResMut<Brush>,
+) {
+    for event in wheel_events.iter() {
+        // Line and pixel units only agree on the direction
+        let step = event.y.signum() as isize;
+        if step == 0 {
+            continue;
+        }
+        if keys.any_pressed([KeyCode::LShift, KeyCode::RShift]) {
+            brush.density = (brush.density + step as f32 * DENSITY_STEP).clamp(MIN_DENSITY, 1.);
+        } else if keys.any_pressed([KeyCode::LControl, KeyCode::RControl]) {
+            brush.scatter = brush.scatter.saturating_add_signed(step).min(MAX_RADIUS);
+        } else {
+            brush.radius = brush.radius.saturating_add_signed(step).min(MAX_RADIUS);
+        }
+    }
+}
+
+/// Paints or erases along the cursor moves with the current [`Tool`]. Strokes
+/// don't start on the UI buttons.
+#[allow(clippy::too_many_arguments)]
+pub fn paint_with_brush(
+    buttons: Res<Input<MouseButton>>,
+    cursor: Res<SnappedPosition>,
+    world_config: Res<WorldConfig>,
+    brush: Res<Brush>,
+    tool: Res<Tool>,
+    element: Res<SelectedElement>,
+    interactions: Query<&Interaction, With<Button>>,
+    mut stroke: ResMut<BrushStroke>,
+    mut map: ResMut<GameMap>,
+) {
+    let position = cursor.to_world_position(world_config.px_per_voxel);
+    let on_button = interactions
+        .iter()
+        .any(|interaction| *interaction != Interaction::None);
+    if buttons.just_pressed(MouseButton::Left) && *tool != Tool::Inspect && !on_button {
+        stroke.start = Some(position);
+        stroke.last = position;
+    }
+    let stroke = &mut *stroke;
+    let rng = &mut stroke.rng;
+    let mut apply = |from, to| match *tool {
+        Tool::Paint => brush.paint(&mut map, from, to, **element, rng),
+        Tool::Erase => brush.erase(&mut map, from, to),
+        Tool::Inspect => 0,
+    };
+    match (brush.shape, stroke.start) {
+        (BrushShape::Line, Some(start)) => {
+            if buttons.just_released(MouseButton::Left) {
+                apply(start, position);
+            }
+        }
+        (_, Some(_)) => {
+            apply(stroke.last, position);
+            stroke.last = position;
+        }
+        (_, None) => (),
+    }
+    if !buttons.pressed(MouseButton::Left) {
+        stroke.start = None;
+    }
+}
+
+/// Draws the outline of the cells the brush covers, the whole line while one is
+/// being drawn and the hovered cell while inspecting. Sprites are only moved when
+/// the outline changes.
+#[allow(clippy::too_many_arguments)]
+pub fn update_brush_preview(
+    mut commands: Commands,
+    cursor: Res<SnappedPosition>,
+    world_config: Res<WorldConfig>,
+    brush: Res<Brush>,
+    tool: Res<Tool>,
+    stroke: Res<BrushStroke>,
+    mut previews: Query<(Entity, &mut Transform), With<BrushPreview>>,
+    mut shown: Local<Vec<WorldPosition>>,
+) {
+    let px_per_voxel = world_config.px_per_voxel;
+    let position = cursor.to_world_position(px_per_voxel);
+    let cells = match (*tool, brush.shape, stroke.start) {
+        (Tool::Inspect, _, _) => vec![position],
+        (_, BrushShape::Line, Some(start)) => brush.stroke(start, position),
+        _ => brush.stamp(position),
+    };
+    let edge = outline(&cells);
+    if *shown == edge {
+        return;
+    }
+
+    let mut translations = edge.iter().map(|cell| {
+        let screen_position =
+            SnappedPosition::from_world_position(cell, px_per_voxel).to_screen_position();
+        // Above the voxel texture
+        Vec3::new(screen_position.x, screen_position.y, 1.)
+    });
+    for (entity, mut transform) in previews.iter_mut() {
+        match translations.next() {
+            Some(translation) => transform.translation = translation,
+            None => commands.entity(entity).despawn(),
+        }
+    }
+    for translation in translations {
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: PREVIEW_COLOR,
+                    custom_size: Some(Vec2::splat(px_per_voxel as f32)),
+                    ..default()
+                },
+                transform: Transform::from_translation(translation),
+                ..default()
+            },
+            BrushPreview,
+        ));
+    }
+    *shown = edge;
+}
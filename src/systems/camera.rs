@@ -1,5 +1,3 @@
-use bevy::render::camera::Viewport;
-
 use bevy::sprite::MaterialMesh2dBundle;
 use bevy::{prelude::*, window::*};
 
@@ -10,84 +8,30 @@ use crate::resources::world::config::WorldConfig;
 use crate::resources::world::player_world_viewpoint::PlayerWorldViewpoint;
 use crate::BACKGROUND;
 
+/// Horizontal camera speed, in pixels per frame.
+const CAMERA_SPEED: f32 = 5.;
+
+/// Moves the camera with the arrow keys. The world has no horizontal edge: the map
+/// is streamed around the camera by `simulation::stream_world`.
 pub fn handle_keyboard(
     keys: Res<Input<KeyCode>>,
-    mut cameras: Query<(&mut Transform, &mut Camera, &mut WorldPosition)>,
+    mut cameras: Query<(&mut Transform, &mut WorldPosition), With<CameraMain>>,
     window_size: Res<ScreenSize>,
     world_config: Res<WorldConfig>,
     mut player_viewpoint: ResMut<PlayerWorldViewpoint>,
 ) {
-    if keys.pressed(KeyCode::Left) || keys.pressed(KeyCode::Right) {
-        let camera_speed = UVec2::new(5, 0);
-        let camera_origin = ScreenPosition {
-            x: window_size.width / 2.0 - world_config.px_per_voxel as f32 / 2.0,
-            y: window_size.height / 2.0 - world_config.px_per_voxel as f32 / 2.0,
-        };
-        let (mut transform, mut camera, mut camera_world_pos) = cameras.single_mut();
-        let padding = camera_speed.x; // TODO add more?
-        let left_limit = 0.0;
-        let right_limit = world_config.pixels_width as f32 - window_size.width;
-        let normalised_main_cam_pos =
-            (ScreenPosition::from_vec3(transform.translation) - camera_origin);
-
-        if keys.pressed(KeyCode::Left) {
-            player_viewpoint.x = (player_viewpoint.x - 5) % world_config.pixels_width as u32;
-        }
-        if keys.pressed(KeyCode::Right) {
-            player_viewpoint.x = (player_viewpoint.x + 5) % world_config.pixels_width as u32;
-        }
-
-        let camera_right_side_pos =
-            player_viewpoint.x as f32 + window_size.width + world_config.px_per_voxel as f32 / 2.0;
-        let camera_left_side_pos = player_viewpoint.x;
-        if keys.pressed(KeyCode::Left) && camera_left_side_pos > 0 {
-            transform.translation -= camera_speed.extend(0).as_vec3();
-        } else if keys.pressed(KeyCode::Right) {
-            if camera_right_side_pos < world_config.pixels_width as f32 {
-                transform.translation += camera_speed.extend(0).as_vec3();
-            } else {
-                println!("Moving camera viewport");
-                camera.viewport.as_mut().map(|v| {
-                    v.physical_size -= camera_speed * 2;
-                    v.physical_position += camera_speed * 2;
-                });
-            }
-        }
-
-        println!(
-            "{:?} <= {:?} <= {:?}",
-            left_limit, player_viewpoint.x, right_limit
-        );
-        /*        if normalised_main_cam_pos.x < left_limit {
-            camera.viewport = Some(Viewport {
-                physical_position: UVec2::new(-normalised_main_cam_pos.x as u32, 0),
-                physical_size: UVec2::new(
-                    (world_config.pixels_width as u32 * 2 + normalised_main_cam_pos.x as u32),
-                    (window_size.height * 2.0) as u32,
-                ),
-                ..default()
-            });
-        } else {*/
-        /*        camera.viewport = Some(Viewport {
-            physical_position: UVec2::new(0, 0),
-            physical_size: UVec2::new(
-                (world_config.pixels_width) as u32 * 2,
-                (window_size.height) as u32 * 2,
-            ),
-            ..default()
-        });*/
-        // }
-
-        *camera_world_pos = normalised_main_cam_pos
-            .to_snapped(&world_config)
-            .to_world_position(world_config.px_per_voxel);
-        println!(
-            "[px width={:?}] {:?} {:?}",
-            world_config.pixels_width,
-            normalised_main_cam_pos.x,
-            camera.logical_viewport_rect().unwrap()
-        );
-    }
+    let direction = match (keys.pressed(KeyCode::Left), keys.pressed(KeyCode::Right)) {
+        (true, false) => -1.,
+        (false, true) => 1.,
+        _ => return,
+    };
+    let (mut transform, mut camera_world_pos) = cameras.single_mut();
+    transform.translation.x += direction * CAMERA_SPEED;
+
+    let normalised_main_cam_pos =
+        ScreenPosition::from_camera(transform.translation, &window_size, &world_config);
+    player_viewpoint.x = normalised_main_cam_pos.x.max(0.) as u32;
+    *camera_world_pos = normalised_main_cam_pos.to_world_position(&world_config);
 }
 
 #[derive(Component)]
@@ -180,18 +124,22 @@ mod tests {
         // Setup app
         let mut app = App::new();
 
-        // Add Score resource
-        //app.insert_resource();
         let voxels_width = 30 + 30;
         let voxels_height = 72;
         let world_config = WorldConfig::new(voxels_width, voxels_height, 10);
 
-        // Add our two systems
-        app //.add_plugins()
-            .init_resource::<ScreenSize>()
+        app.init_resource::<ScreenSize>()
+            .init_resource::<PlayerWorldViewpoint>()
             .insert_resource(world_config)
-            .add_system(handle_window_resize)
             .add_system(handle_keyboard);
+        let camera = app
+            .world
+            .spawn((
+                Transform::from_xyz(100., 0., 0.),
+                WorldPosition::default(),
+                CameraMain,
+            ))
+            .id();
 
         // Setup test resource
         let mut input = Input::<KeyCode>::default();
@@ -202,12 +150,9 @@ mod tests {
         app.update();
 
         // Check resulting changes
-        assert!(app
-            .world
-            .query::<&Camera>()
-            .single(&app.world)
-            .viewport
-            .is_some());
-        //assert_eq!(app.world.query::<&Camera>().unwrap(), 4);
+        assert_eq!(
+            app.world.get::<Transform>(camera).unwrap().translation.x,
+            100. - CAMERA_SPEED
+        );
     }
 }
@@ -0,0 +1,7 @@
+pub mod brush;
+pub mod camera;
+pub mod inputs;
+pub mod rendering;
+pub mod simulation;
+pub mod startup;
+pub mod tools;
@@ -0,0 +1,163 @@
+use std::num::NonZeroU32;
+
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_resource::{
+    Extent3d, ImageCopyTexture, ImageDataLayout, Origin3d, TextureAspect, TextureDimension,
+    TextureFormat,
+};
+use bevy::render::renderer::RenderQueue;
+use bevy::render::texture::ImageSampler;
+use bevy::render::{Extract, ExtractSchedule, RenderApp, RenderSet};
+use bevy::sprite::Anchor;
+
+use sandbase_core::{GameMap, MapTexture, TextureRegion};
+
+use crate::components::voxels::VoxelTexture;
+use crate::resources::world::config::WorldConfig;
+use crate::systems::simulation;
+
+/// Draws the whole map with a single sprite showing the [`VoxelTexture`]. When
+/// the app renders, only the chunks that changed are copied to the GPU texture;
+/// without a render app, as in headless runs, the image asset is rewritten
+/// instead.
+pub struct VoxelRenderPlugin;
+
+impl Plugin for VoxelRenderPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(setup_voxel_texture.after(simulation::load_world))
+            .add_system(render_voxel_world.after(simulation::update_voxel_world));
+        match app.get_sub_app_mut(RenderApp) {
+            Ok(render_app) => {
+                render_app
+                    .init_resource::<PendingUploads>()
+                    .add_system(extract_voxel_uploads.in_schedule(ExtractSchedule))
+                    .add_system(upload_voxel_regions.in_set(RenderSet::Queue));
+            }
+            Err(_) => {
+                app.add_system(copy_voxel_texture.after(render_voxel_world));
+            }
+        }
+    }
+}
+
+fn setup_voxel_texture(
+    mut commands: Commands,
+    map: Res<GameMap>,
+    world_config: Res<WorldConfig>,
+    images: Option<ResMut<Assets<Image>>>,
+) {
+    let texture = MapTexture::from_map(&map);
+    let mut image = Image::new(
+        Extent3d {
+            width: texture.width as u32,
+            height: texture.height as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        texture.pixels().to_vec(),
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    image.sampler_descriptor = ImageSampler::nearest();
+    let image = images.map_or_else(Handle::default, |mut images| images.add(image));
+
+    // Cells are centered on their snapped position, the sprite starts half a cell
+    // before the first one
+    let px_per_voxel = world_config.px_per_voxel as f32;
+    commands.spawn(SpriteBundle {
+        sprite: Sprite {
+            custom_size: Some(Vec2::new(
+                texture.width as f32 * px_per_voxel,
+                texture.height as f32 * px_per_voxel,
+            )),
+            anchor: Anchor::BottomLeft,
+            ..default()
+        },
+        texture: image.clone(),
+        transform: Transform::from_xyz(-px_per_voxel / 2., -px_per_voxel / 2., 0.),
+        ..default()
+    });
+    commands.insert_resource(VoxelTexture {
+        texture,
+        image,
+        uploads: Vec::new(),
+    });
+}
+
+/// Repaints the cells changed since the last frame.
+pub fn render_voxel_world(mut map: ResMut<GameMap>, voxel_texture: Option<ResMut<VoxelTexture>>) {
+    let mut voxel_texture = match voxel_texture {
+        Some(voxel_texture) => voxel_texture,
+        None => return,
+    };
+    let changes = map.take_changes();
+    voxel_texture.texture.paint(&map, &changes);
+    voxel_texture.uploads = voxel_texture.texture.take_regions();
+}
+
+/// Writes the whole texture to the image asset, for apps without a render app.
+fn copy_voxel_texture(voxel_texture: Res<VoxelTexture>, images: Option<ResMut<Assets<Image>>>) {
+    let mut images = match images {
+        Some(images) if !voxel_texture.uploads.is_empty() => images,
+        _ => return,
+    };
+    if let Some(image) = images.get_mut(&voxel_texture.image) {
+        image.data.copy_from_slice(voxel_texture.texture.pixels());
+    }
+}
+
+/// Regions waiting to be copied to the GPU texture, kept until the image is on
+/// the GPU.
+#[derive(Default, Resource)]
+struct PendingUploads {
+    image: Handle<Image>,
+    regions: Vec<TextureRegion>,
+}
+
+fn extract_voxel_uploads(
+    voxel_texture: Extract<Option<Res<VoxelTexture>>>,
+    mut pending: ResMut<PendingUploads>,
+) {
+    if let Some(voxel_texture) = voxel_texture.as_ref() {
+        pending.image = voxel_texture.image.clone_weak();
+        pending
+            .regions
+            .extend(voxel_texture.uploads.iter().cloned());
+    }
+}
+
+fn upload_voxel_regions(
+    mut pending: ResMut<PendingUploads>,
+    images: Res<RenderAssets<Image>>,
+    queue: Res<RenderQueue>,
+) {
+    let gpu_image = match images.get(&pending.image) {
+        Some(gpu_image) => gpu_image,
+        None => return,
+    };
+    for region in pending.regions.drain(..) {
+        queue.write_texture(
+            ImageCopyTexture {
+                texture: &gpu_image.texture,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: region.x,
+                    y: region.y,
+                    z: 0,
+                },
+                aspect: TextureAspect::All,
+            },
+            &region.pixels,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(region.width * 4),
+                rows_per_image: None,
+            },
+            Extent3d {
+                width: region.width,
+                height: region.height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
@@ -0,0 +1,238 @@
+use bevy::app::AppExit;
+use bevy::prelude::*;
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+
+use sandbase_core::{
+    export_png, import_png, load_world as load_save, save_world as write_save, ChunkStore, GameMap,
+    ImageError, ImportOptions, SaveError, Simulation, SimulationClock, StreamedWorld,
+    TerrainConfig, TerrainGenerator, WorldInfo, CHUNK_SIZE,
+};
+
+use crate::components::positions::screen_position::ScreenPosition;
+use crate::components::positions::world_position::WorldPosition;
+use crate::components::voxels::Voxel;
+use crate::resources::window::size::ScreenSize;
+use crate::resources::world::config::WorldConfig;
+use crate::resources::world::player_world_viewpoint::PlayerWorldViewpoint;
+use crate::systems::camera::CameraMain;
+use crate::{LEVEL_PATH, SAVE_PATH, SCREENSHOT_PATH, WORLD_PATH};
+
+const FAST_FORWARD: f32 = 4.;
+
+/// P pauses/resumes the simulation, N runs a single tick and F toggles fast-forward.
+pub fn handle_clock_keys(keys: Res<Input<KeyCode>>, mut clock: ResMut<SimulationClock>) {
+    if keys.just_pressed(KeyCode::P) {
+        clock.toggle_pause();
+    }
+    if keys.just_pressed(KeyCode::N) {
+        clock.step_once();
+    }
+    if keys.just_pressed(KeyCode::F) {
+        clock.fast_forward = if clock.fast_forward > 1. {
+            1.
+        } else {
+            FAST_FORWARD
+        };
+    }
+}
+
+/// Loads or generates the chunks around the starting point.
+pub fn load_world(world: Res<StreamedWorld>, mut map: ResMut<GameMap>) {
+    if let Err(e) = world.load_all(&mut map) {
+        println!("Can't load the world: {}", e);
+    }
+}
+
+/// Keeps the camera over the middle chunk of the map: when it gets a chunk away,
+/// the map scrolls and the camera jumps back by the same distance, so the view
+/// doesn't move.
+pub fn stream_world(
+    mut world: ResMut<StreamedWorld>,
+    mut map: ResMut<GameMap>,
+    world_config: Res<WorldConfig>,
+    window_size: Res<ScreenSize>,
+    mut player_viewpoint: ResMut<PlayerWorldViewpoint>,
+    mut cameras: Query<(&mut Transform, &mut WorldPosition), With<CameraMain>>,
+) {
+    let (mut transform, mut camera_world_pos) = match cameras.get_single_mut() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let px_per_voxel = world_config.px_per_voxel as f32;
+    // Voxels are drawn centered on their column, the camera can be left of the map
+    let center = ((transform.translation.x + px_per_voxel / 2.) / px_per_voxel).floor() as isize;
+    match world.follow(&mut map, center) {
+        Ok(0) => (),
+        Ok(shift) => {
+            transform.translation.x -= (shift * CHUNK_SIZE as isize) as f32 * px_per_voxel;
+            let normalised_main_cam_pos =
+                ScreenPosition::from_camera(transform.translation, &window_size, &world_config);
+            player_viewpoint.x = normalised_main_cam_pos.x.max(0.) as u32;
+            *camera_world_pos = normalised_main_cam_pos.to_world_position(&world_config);
+        }
+        Err(e) => println!("Can't stream the world: {}", e),
+    }
+}
+
+/// Saves the loaded chunks when the game exits.
+pub fn save_world(exits: EventReader<AppExit>, world: Res<StreamedWorld>, map: Res<GameMap>) {
+    if exits.is_empty() {
+        return;
+    }
+    if let Err(e) = world.save_all(&map) {
+        println!("Can't save the world: {}", e);
+    }
+}
+
+/// F5 saves the simulation, the camera and the chunk columns out of the map to the
+/// save file, F9 puts them back.
+#[allow(clippy::too_many_arguments)]
+pub fn handle_save_keys(
+    keys: Res<Input<KeyCode>>,
+    mut map: ResMut<GameMap>,
+    mut world: ResMut<StreamedWorld>,
+    mut terrain_config: ResMut<TerrainConfig>,
+    world_config: Res<WorldConfig>,
+    window_size: Res<ScreenSize>,
+    mut player_viewpoint: ResMut<PlayerWorldViewpoint>,
+    mut cameras: Query<(&mut Transform, &mut WorldPosition), With<CameraMain>>,
+) {
+    let (mut transform, mut camera_world_pos) = match cameras.get_single_mut() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    if keys.just_pressed(KeyCode::F5) {
+        let columns = match world.stored_columns(&map) {
+            Ok(columns) => columns,
+            Err(e) => {
+                println!("Can't save the world: {}", e);
+                return;
+            }
+        };
+        let info = WorldInfo {
+            px_per_voxel: world_config.px_per_voxel as u32,
+            first_column: world.first_column,
+            camera_x: transform.translation.x,
+            viewpoint_x: player_viewpoint.x,
+            terrain: terrain_config.clone(),
+            columns,
+        };
+        let saved = fs::create_dir_all("saves")
+            .and_then(|_| File::create(SAVE_PATH))
+            .map_err(SaveError::from)
+            .and_then(|file| write_save(&mut BufWriter::new(file), &map, &info));
+        match saved {
+            Ok(()) => println!("World saved to {}", SAVE_PATH),
+            Err(e) => println!("Can't save the world: {}", e),
+        }
+    }
+    if keys.just_pressed(KeyCode::F9) {
+        let registry = map.registry().clone();
+        let loaded = File::open(SAVE_PATH)
+            .map_err(SaveError::from)
+            .and_then(|file| load_save(&mut BufReader::new(file), registry));
+        let (loaded_map, info) = match loaded {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                println!("Can't load {}: {}", SAVE_PATH, e);
+                return;
+            }
+        };
+        if info.px_per_voxel as usize != world_config.px_per_voxel
+            || (loaded_map.width, loaded_map.height) != (map.width, map.height)
+        {
+            println!("Can't load {}: saved with another world size", SAVE_PATH);
+            return;
+        }
+        let streamed = StreamedWorld::new(
+            ChunkStore::new(WORLD_PATH),
+            TerrainGenerator::new(info.terrain.clone()),
+            loaded_map.width,
+        );
+        *world = match streamed {
+            Ok(streamed) => streamed,
+            Err(e) => {
+                println!("Can't load {}: {}", SAVE_PATH, e);
+                return;
+            }
+        };
+        *map = loaded_map;
+        world.first_column = info.first_column;
+        if let Err(e) = world.restore_columns(&info.columns, map.registry()) {
+            println!("Can't restore the chunk columns of {}: {}", SAVE_PATH, e);
+        }
+        *terrain_config = info.terrain;
+
+        transform.translation.x = info.camera_x;
+        player_viewpoint.x = info.viewpoint_x;
+        *camera_world_pos =
+            ScreenPosition::from_camera(transform.translation, &window_size, &world_config)
+                .to_world_position(&world_config);
+        println!("World loaded from {}", SAVE_PATH);
+    }
+}
+
+/// F12 writes a screenshot of the map, one pixel per voxel, and F10 replaces the
+/// map with the level drawn in the level image, from its bottom-left corner.
+pub fn handle_image_keys(keys: Res<Input<KeyCode>>, mut map: ResMut<GameMap>) {
+    if keys.just_pressed(KeyCode::F12) {
+        let exported = fs::create_dir_all("saves")
+            .and_then(|_| File::create(SCREENSHOT_PATH))
+            .map_err(ImageError::from)
+            .and_then(|file| export_png(BufWriter::new(file), &map));
+        match exported {
+            Ok(()) => println!("Screenshot saved to {}", SCREENSHOT_PATH),
+            Err(e) => println!("Can't save the screenshot: {}", e),
+        }
+    }
+    if keys.just_pressed(KeyCode::F10) {
+        let registry = map.registry().clone();
+        let options = ImportOptions {
+            max_size: Some((map.width, map.height)),
+            ..ImportOptions::default()
+        };
+        let imported = File::open(LEVEL_PATH)
+            .map_err(ImageError::from)
+            .and_then(|file| import_png(BufReader::new(file), registry, &options));
+        let level = match imported {
+            Ok(level) => level,
+            Err(e) => {
+                println!("Can't import {}: {}", LEVEL_PATH, e);
+                return;
+            }
+        };
+        for x in 0..map.width {
+            for y in 0..map.height {
+                let world_position = WorldPosition::new(x, y);
+                match level.get_cell(&world_position) {
+                    Some(voxel @ Voxel::of { .. }) => map.set_cell(&world_position, &voxel),
+                    _ => map.delete_cell(&world_position),
+                }
+            }
+        }
+        println!("Level imported from {}", LEVEL_PATH);
+    }
+}
+
+pub fn update_voxel_world(
+    time: Res<Time>,
+    mut clock: ResMut<SimulationClock>,
+    mut simulation: ResMut<Simulation>,
+    mut map: ResMut<GameMap>,
+) {
+    let ticks = clock.advance(time.delta_seconds());
+    if ticks == 0 {
+        return;
+    }
+    if let Err(e) = simulation.run(&mut map, ticks as usize) {
+        // e.g. a loaded level uses elements the GPU can't simulate
+        println!(
+            "Can't simulate on {}: {}, using the CPU",
+            simulation.name(),
+            e
+        );
+        *simulation = Simulation::default();
+    }
+}
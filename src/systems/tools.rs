@@ -0,0 +1,259 @@
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+
+use sandbase_core::{Element, GameMap, Voxel};
+
+use crate::components::positions::snapped_position::SnappedPosition;
+use crate::resources::world::config::WorldConfig;
+use crate::systems::brush::SelectedElement;
+
+const FONT_PATH: &str = "fonts/FiraSans-Bold.ttf";
+const BUTTON_COLOR: Color = Color::rgb(0.15, 0.15, 0.15);
+const SELECTED_BUTTON_COLOR: Color = Color::rgb(0.35, 0.35, 0.35);
+const TEXT_COLOR: Color = Color::rgb(0.9, 0.9, 0.9);
+const TOOLTIP_BACKGROUND: Color = Color::rgba(0., 0., 0., 0.7);
+/// Distance between the cursor and the tooltip, in pixels.
+const TOOLTIP_OFFSET: f32 = 16.;
+const BUTTON_WIDTH: f32 = 120.;
+const BUTTON_HEIGHT: f32 = 40.;
+/// Space between the buttons and from the window edges, in pixels.
+const BUTTON_MARGIN: f32 = 5.;
+
+/// Buttons picking what the left mouse button does and the element it paints, and
+/// the tooltip of the inspect tool.
+pub struct ToolsPlugin;
+
+impl Plugin for ToolsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Tool>()
+            .add_startup_system(setup_tool_buttons)
+            .add_startup_system(setup_element_buttons)
+            .add_startup_system(setup_inspect_tooltip)
+            .add_system(handle_tool_buttons)
+            .add_system(handle_element_buttons)
+            .add_system(update_inspect_tooltip);
+    }
+}
+
+/// What the left mouse button does on the map.
+#[derive(Resource, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Tool {
+    /// Paints the selected element with the brush.
+    #[default]
+    Paint,
+    /// Deletes the voxels under the brush.
+    Erase,
+    /// Describes the voxel under the cursor.
+    Inspect,
+}
+
+impl Tool {
+    const ALL: [Tool; 3] = [Tool::Paint, Tool::Erase, Tool::Inspect];
+
+    fn label(self) -> &'static str {
+        match self {
+            Tool::Paint => "Paint",
+            Tool::Erase => "Erase",
+            Tool::Inspect => "Inspect",
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct ToolButton(Tool);
+
+/// Button selecting the element painted by the brush.
+#[derive(Component)]
+pub struct ElementButton(Element);
+
+#[derive(Component)]
+pub struct InspectTooltip;
+
+fn setup_tool_buttons(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load(FONT_PATH);
+    commands
+        .spawn(button_column(2. * BUTTON_MARGIN))
+        .with_children(|parent| {
+            for tool in Tool::ALL {
+                let selected = tool == Tool::default();
+                spawn_button(parent, tool.label(), &font, selected, ToolButton(tool));
+            }
+        });
+}
+
+/// One button per element of the map, left of the tool buttons.
+fn setup_element_buttons(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    map: Res<GameMap>,
+    selected: Res<SelectedElement>,
+) {
+    let font = asset_server.load(FONT_PATH);
+    commands
+        .spawn(button_column(3. * BUTTON_MARGIN + BUTTON_WIDTH))
+        .with_children(|parent| {
+            for (element, definition) in map.registry().iter() {
+                let is_selected = element == **selected;
+                spawn_button(
+                    parent,
+                    &definition.name,
+                    &font,
+                    is_selected,
+                    ElementButton(element),
+                );
+            }
+        });
+}
+
+/// Column of buttons from the top of the window, `right` pixels from its right edge.
+fn button_column(right: f32) -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                right: Val::Px(right),
+                top: Val::Px(2. * BUTTON_MARGIN),
+                ..default()
+            },
+            flex_direction: FlexDirection::Column,
+            ..default()
+        },
+        ..default()
+    }
+}
+
+fn spawn_button(
+    parent: &mut ChildBuilder,
+    label: &str,
+    font: &Handle<Font>,
+    selected: bool,
+    marker: impl Component,
+) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    size: Size::new(Val::Px(BUTTON_WIDTH), Val::Px(BUTTON_HEIGHT)),
+                    margin: UiRect::bottom(Val::Px(BUTTON_MARGIN)),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                background_color: button_color(selected).into(),
+                ..default()
+            },
+            marker,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                label,
+                TextStyle {
+                    font: font.clone(),
+                    font_size: 24.,
+                    color: TEXT_COLOR,
+                },
+            ));
+        });
+}
+
+fn setup_inspect_tooltip(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let mut tooltip = TextBundle::from_section(
+        "",
+        TextStyle {
+            font: asset_server.load(FONT_PATH),
+            font_size: 18.,
+            color: TEXT_COLOR,
+        },
+    )
+    .with_style(Style {
+        position_type: PositionType::Absolute,
+        padding: UiRect::all(Val::Px(4.)),
+        ..default()
+    })
+    .with_background_color(TOOLTIP_BACKGROUND);
+    tooltip.visibility = Visibility::Hidden;
+    commands.spawn((tooltip, InspectTooltip));
+}
+
+fn button_color(selected: bool) -> Color {
+    if selected {
+        SELECTED_BUTTON_COLOR
+    } else {
+        BUTTON_COLOR
+    }
+}
+
+pub fn handle_tool_buttons(
+    mut tool: ResMut<Tool>,
+    interactions: Query<(&Interaction, &ToolButton), Changed<Interaction>>,
+    mut buttons: Query<(&ToolButton, &mut BackgroundColor)>,
+) {
+    for (interaction, button) in interactions.iter() {
+        if *interaction == Interaction::Clicked {
+            *tool = button.0;
+        }
+    }
+    if tool.is_changed() {
+        for (button, mut color) in buttons.iter_mut() {
+            *color = button_color(button.0 == *tool).into();
+        }
+    }
+}
+
+pub fn handle_element_buttons(
+    mut selected: ResMut<SelectedElement>,
+    interactions: Query<(&Interaction, &ElementButton), Changed<Interaction>>,
+    mut buttons: Query<(&ElementButton, &mut BackgroundColor)>,
+) {
+    for (interaction, button) in interactions.iter() {
+        if *interaction == Interaction::Clicked {
+            **selected = button.0;
+        }
+    }
+    if selected.is_changed() {
+        for (button, mut color) in buttons.iter_mut() {
+            *color = button_color(button.0 == **selected).into();
+        }
+    }
+}
+
+/// Shows the element, kind, temperature and velocity of the voxel under the
+/// cursor next to it while inspecting.
+pub fn update_inspect_tooltip(
+    tool: Res<Tool>,
+    map: Res<GameMap>,
+    cursor: Res<SnappedPosition>,
+    world_config: Res<WorldConfig>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut tooltips: Query<(&mut Text, &mut Style, &mut Visibility), With<InspectTooltip>>,
+) {
+    let (mut text, mut style, mut visibility) = match tooltips.get_single_mut() {
+        Ok(tooltip) => tooltip,
+        Err(_) => return,
+    };
+    let world_position = cursor.to_world_position(world_config.px_per_voxel);
+    let window_cursor = windows
+        .get_single()
+        .ok()
+        .and_then(|window| window.cursor_position());
+    let (data, window_cursor) = match (*tool, map.get_cell(&world_position), window_cursor) {
+        (Tool::Inspect, Some(Voxel::of { data }), Some(window_cursor)) => (data, window_cursor),
+        _ => {
+            *visibility = Visibility::Hidden;
+            return;
+        }
+    };
+
+    let definition = map.registry().get(data.element);
+    text.sections[0].value = format!(
+        "{} ({:?})\n{:.1} °C\n{:.2} cells/tick",
+        definition.name, definition.kind, data.temperature, data.velocity
+    );
+    // The window cursor starts from the bottom-left corner
+    style.position = UiRect {
+        left: Val::Px(window_cursor.x + TOOLTIP_OFFSET),
+        bottom: Val::Px(window_cursor.y + TOOLTIP_OFFSET),
+        ..default()
+    };
+    *visibility = Visibility::Visible;
+}